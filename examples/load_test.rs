@@ -0,0 +1,142 @@
+//! Hammers a running pool with job requests and submissions carrying real shares.
+//!
+//! Each worker is a thread pretending to be a different student, so with per-submitter
+//! locking the throughput should grow with the number of workers until the server runs out
+//! of threads or disk. Every submission holds a share, so the run also measures the path that
+//! credits shares, records hashes and saves rewards. The workers have no API keys, hash with
+//! SHA-256 and are all on one IP, so run the pool with `--require-api-key false
+//! --require-signature false --ip-rate-limit 0 --job-rate-limit 0`, the default algorithm and
+//! a low difficulty, e.g. `--min-zero-bits 8 --max-zero-bits 8`.
+//!
+//!     cargo run --release --example load_test -- 127.0.0.1:9876 1,2,4,8,16 5
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let address = args.get(1).cloned().unwrap_or_else(|| String::from("127.0.0.1:9876"));
+    let worker_counts: Vec<usize> = args.get(2)
+        .map(|s| s.split(',').map(|n| n.parse().expect("worker counts must be numbers")).collect())
+        .unwrap_or_else(|| vec![1, 2, 4, 8, 16]);
+    let seconds: u64 = args.get(3).map(|s| s.parse().expect("seconds must be a number")).unwrap_or(5);
+
+    println!("workers  round trips/s  accepted shares/s");
+    for workers in worker_counts {
+        let deadline = Instant::now() + Duration::from_secs(seconds);
+        let threads: Vec<_> = (0..workers)
+            .map(|i| {
+                let address = address.clone();
+                let student_number = format!("load-{}-{}", workers, i);
+                std::thread::spawn(move || worker(&address, &student_number, deadline))
+            })
+            .collect();
+        let (round_trips, shares) = threads.into_iter()
+            .map(|t| t.join().unwrap())
+            .fold((0, 0), |(round_trips, shares), (r, s)| (round_trips + r, shares + s));
+        println!(
+            "{:7}  {:13.1}  {:17.1}",
+            workers,
+            round_trips as f64 / seconds as f64,
+            shares as f64 / seconds as f64,
+        );
+    }
+}
+
+/// Requests jobs, finds a share in each and submits it until the deadline. Returns the number
+/// of completed round trips and of accepted shares.
+fn worker(address: &str, student_number: &str, deadline: Instant) -> (u64, u64) {
+    let stream = TcpStream::connect(address).expect("could not connect to the pool");
+    stream.set_nodelay(true).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    let mut round_trips = 0;
+    let mut shares = 0;
+    while Instant::now() < deadline {
+        let request = json!({ "student_number": student_number, "name": "load" });
+        let job = post(&mut writer, &mut reader, "/job/request", &request);
+        let job = &job["Success"];
+        assert_eq!(job["algorithm"], "sha256", "the load test only hashes SHA-256 jobs: {}", job);
+        // The rest of the job is queued again and handed out by a later request.
+        let (solutions, searched_end) = find_share(student_number, job);
+        let submittion = json!({
+            "job_n": job["number"],
+            "name": "load",
+            "student_number": student_number,
+            "thread_hashes_per_second": 1_000_000.0,
+            "total_hashes_per_second": 4_000_000.0,
+            "nounce_start": job["nounce_start"],
+            "nounce_end": searched_end,
+            "solutions": solutions,
+        });
+        let response = post(&mut writer, &mut reader, "/job/submit", &submittion);
+        shares += response["solutions"].as_array()
+            .map_or(0, |reports| reports.iter().filter(|report| report["verdict"] == "accepted").count()) as u64;
+        round_trips += 1;
+    }
+    (round_trips, shares)
+}
+
+/// Hashes the job's nounces in order until one makes a share. Returns the share, if one was
+/// found, and the end of the searched range.
+fn find_share(student_number: &str, job: &Value) -> (Vec<Value>, u64) {
+    let challenge = job["challenge"].as_str().expect("job has no challenge");
+    let zero_bits = job["zero_bits"].as_u64().expect("job has no difficulty") as u32;
+    let nounce_start = job["nounce_start"].as_u64().expect("job has no nounce_start");
+    let nounce_end = job["nounce_end"].as_u64().expect("job has no nounce_end");
+    for nounce in nounce_start..nounce_end {
+        let digest = Sha256::digest(format!("{}{}{}", challenge, student_number, nounce).as_bytes());
+        if leading_zero_bits(&digest) >= zero_bits {
+            let hash: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let share = json!({ "sha256": hash, "nounce": nounce.to_string(), "time": 0.0 });
+            return (vec![share], nounce + 1);
+        }
+    }
+    (vec![], nounce_end)
+}
+
+fn leading_zero_bits(digest: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in digest {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+/// Sends a keep-alive HTTP/1.1 POST and reads back the JSON body.
+fn post(writer: &mut TcpStream, reader: &mut BufReader<TcpStream>, path: &str, body: &Value) -> Value {
+    let body = body.to_string();
+    write!(
+        writer,
+        "POST {} HTTP/1.1\r\nHost: pool\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        path,
+        body.len(),
+        body,
+    ).expect("could not send request");
+
+    let mut content_length = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).expect("could not read response");
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().expect("bad content-length");
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).expect("could not read response body");
+    serde_json::from_slice(&body).expect("response was not JSON")
+}
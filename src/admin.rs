@@ -0,0 +1,331 @@
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse, web::Json};
+
+use crate::app::ApplicationData;
+use crate::hashrate::SubmitterHashrate;
+use crate::packets;
+use crate::error::{ApiError, ErrorCode};
+use crate::submitter::{invalid_ratio, Submitter};
+
+type AppData = web::Data<ApplicationData>;
+
+/// Every `/admin` route. Each needs the admin token.
+pub fn scope() -> actix_web::Scope {
+    web::scope("/admin")
+        .service(list_submitters)
+        .service(reset_submitter)
+        .service(delete_submitter)
+        .service(requeue_submitter)
+        .service(lift_quarantine)
+        .service(list_bans)
+        .service(ban)
+        .service(unban)
+        .service(reload_credentials)
+        .service(issue_credentials)
+        .service(clear_best)
+        .service(save)
+}
+
+fn ok(msg: String) -> HttpResponse {
+    HttpResponse::Ok().json(packets::CommandResponse { ok: true, msg: Some(msg) })
+}
+
+fn not_found(msg: &str) -> ApiError {
+    ApiError::new(ErrorCode::NotFound, msg)
+}
+
+#[get("/submitters")]
+pub async fn list_submitters(req: HttpRequest, data: AppData) -> Result<HttpResponse, ApiError> {
+    data.authenticate_admin(&req)?;
+    let app = data.into_inner();
+    let submitters = web::block(move || Ok::<_, ApiError>(admin_submitters(&app))).await?;
+    Ok(HttpResponse::Ok().json(submitters))
+}
+
+/// Body of `GET /admin/submitters`. Locks every submitter, so it runs on the blocking thread
+/// pool.
+fn admin_submitters(app: &ApplicationData) -> Vec<packets::AdminSubmitter> {
+    let bans = app.bans();
+    let now = crate::util::get_time();
+    let mut submitters: Vec<packets::AdminSubmitter> = app.all_submitters().iter()
+        .map(|shared| {
+            let submitter = shared.lock();
+            let hashrate = SubmitterHashrate::of(&submitter, now);
+            packets::AdminSubmitter {
+                student_number: submitter.student_number.clone(),
+                banned: bans.get(&submitter.student_number).cloned(),
+                accepted_shares_count: submitter.accepted_shares_count,
+                invalid_shares_count: submitter.invalid_shares_count,
+                invalid_ratio: invalid_ratio(submitter.accepted_shares_count, submitter.invalid_shares_count),
+                work: submitter.work,
+                reported_total_hashrate: hashrate.reported,
+                effective_hashrate: hashrate.effective,
+                next_nounce: submitter.next_nounce,
+                pending_jobs: submitter.pending_jobs.len(),
+                unfinished_jobs: submitter.unfinished_jobs.len(),
+                machines: submitter.machines.iter()
+                    .map(|machine| packets::AdminMachine {
+                        name: machine.name.clone(),
+                        liveness: machine.liveness(now),
+                        last_seen: machine.last_seen,
+                        pending_jobs: submitter.pending_jobs_of(&machine.name).count(),
+                        target_zero_bits: machine.target_zero_bits,
+                        accepted_shares_count: machine.accepted_shares_count,
+                        invalid_shares_count: machine.invalid_shares_count,
+                        recent_invalid_ratio: machine.recent_invalid_ratio(),
+                        quarantine: machine.quarantine.clone(),
+                        work: machine.work,
+                        reported_total_hashrate: machine.reported_total_hashrate,
+                        effective_hashrate: machine.work_history.effective(now),
+                    })
+                    .collect(),
+            }
+        })
+        .collect();
+    submitters.sort_by(|a, b| a.student_number.cmp(&b.student_number));
+    submitters
+}
+
+/// Starts the submitter over: no machines, jobs, shares or coverage. Reward balances are kept.
+#[post("/submitters/{student_number}/reset")]
+pub async fn reset_submitter(req: HttpRequest, data: AppData, student_number: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    data.authenticate_admin(&req)?;
+    let app = data.into_inner();
+    let student_number = student_number.into_inner();
+    let student_number = web::block(move || {
+        let shared = app.get_submitter(&student_number).ok_or_else(|| not_found("no such submitter"))?;
+        let mut submitter = shared.lock();
+        if submitter.deleted {
+            return Err(not_found("no such submitter"));
+        }
+        *submitter = Submitter::new(&student_number);
+        app.save_submitter(&submitter)?;
+        Ok::<_, ApiError>(student_number)
+    }).await?;
+    Ok(ok(format!("reset {}", student_number)))
+}
+
+/// Deletes the submitter and its solution files. Reward balances are kept.
+#[delete("/submitters/{student_number}")]
+pub async fn delete_submitter(req: HttpRequest, data: AppData, student_number: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    data.authenticate_admin(&req)?;
+    let app = data.into_inner();
+    let student_number = student_number.into_inner();
+    let name = student_number.clone();
+    if !web::block(move || app.delete_submitter(&name)).await? {
+        return Err(not_found("no such submitter"));
+    }
+    Ok(ok(format!("deleted {}", student_number)))
+}
+
+/// Moves every job leased to the submitter back into its unfinished jobs.
+#[post("/submitters/{student_number}/requeue")]
+pub async fn requeue_submitter(req: HttpRequest, data: AppData, student_number: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    data.authenticate_admin(&req)?;
+    let app = data.into_inner();
+    let requeued = web::block(move || {
+        let shared = app.get_submitter(&student_number).ok_or_else(|| not_found("no such submitter"))?;
+        let mut submitter = shared.lock();
+        let requeued = submitter.requeue_pending();
+        app.save_submitter(&submitter)?;
+        Ok::<_, ApiError>(requeued)
+    }).await?;
+    Ok(ok(format!("requeued {} jobs", requeued)))
+}
+
+/// Lets a quarantined machine take jobs again.
+#[delete("/submitters/{student_number}/machines/{name}/quarantine")]
+pub async fn lift_quarantine(req: HttpRequest, data: AppData, path: web::Path<(String, String)>) -> Result<HttpResponse, ApiError> {
+    data.authenticate_admin(&req)?;
+    let app = data.into_inner();
+    let (student_number, name) = path.into_inner();
+    let machine = name.clone();
+    web::block(move || {
+        let shared = app.get_submitter(&student_number).ok_or_else(|| not_found("no such submitter"))?;
+        let mut submitter = shared.lock();
+        if !submitter.lift_quarantine(&machine) {
+            return Err(not_found("machine is not quarantined"));
+        }
+        app.save_submitter(&submitter)?;
+        Ok::<_, ApiError>(())
+    }).await?;
+    Ok(ok(format!("lifted the quarantine of {}", name)))
+}
+
+#[get("/bans")]
+pub async fn list_bans(req: HttpRequest, data: AppData) -> Result<HttpResponse, ApiError> {
+    data.authenticate_admin(&req)?;
+    Ok(HttpResponse::Ok().json(data.bans()))
+}
+
+#[put("/bans/{student_number}")]
+pub async fn ban(
+    req: HttpRequest,
+    data: AppData,
+    student_number: web::Path<String>,
+    ban_request: Json<packets::BanRequest>,
+) -> Result<HttpResponse, ApiError> {
+    data.authenticate_admin(&req)?;
+    let app = data.into_inner();
+    let student_number = student_number.into_inner();
+    let name = student_number.clone();
+    web::block(move || app.ban(&name, &ban_request.reason)).await?;
+    Ok(ok(format!("banned {}", student_number)))
+}
+
+#[delete("/bans/{student_number}")]
+pub async fn unban(req: HttpRequest, data: AppData, student_number: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    data.authenticate_admin(&req)?;
+    let app = data.into_inner();
+    let student_number = student_number.into_inner();
+    let name = student_number.clone();
+    if !web::block(move || app.unban(&name)).await? {
+        return Err(not_found("student number is not banned"));
+    }
+    Ok(ok(format!("unbanned {}", student_number)))
+}
+
+/// Replaces any key and secret the student had. A student need not have a submitter yet.
+#[put("/credentials/{student_number}")]
+pub async fn issue_credentials(req: HttpRequest, data: AppData, student_number: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    data.authenticate_admin(&req)?;
+    let app = data.into_inner();
+    let student_number = student_number.into_inner();
+    let name = student_number.clone();
+    let (key, secret) = web::block(move || app.issue_credentials(&name)).await?;
+    Ok(HttpResponse::Ok().json(packets::IssuedCredentials { student_number, key, secret }))
+}
+
+/// Picks up keys issued with `--issue-key` while the server was running.
+#[post("/credentials/reload")]
+pub async fn reload_credentials(req: HttpRequest, data: AppData) -> Result<HttpResponse, ApiError> {
+    data.authenticate_admin(&req)?;
+    let app = data.into_inner();
+    if !web::block(move || Ok::<_, ApiError>(app.reload_credentials())).await? {
+        return Err(not_found("no credentials in storage"));
+    }
+    Ok(ok(String::from("reloaded the API keys")))
+}
+
+#[delete("/best")]
+pub async fn clear_best(req: HttpRequest, data: AppData) -> Result<HttpResponse, ApiError> {
+    data.authenticate_admin(&req)?;
+    let app = data.into_inner();
+    web::block(move || app.clear_best()).await?;
+    Ok(ok(String::from("cleared the best solution")))
+}
+
+/// Writes everything held in memory to storage.
+#[post("/save")]
+pub async fn save(req: HttpRequest, data: AppData) -> Result<HttpResponse, ApiError> {
+    data.authenticate_admin(&req)?;
+    let app = data.into_inner();
+    web::block(move || app.save_all()).await?;
+    Ok(ok(String::from("saved")))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::{test, App};
+
+    use super::*;
+    use crate::credentials::{Credentials, API_KEY_HEADER};
+    use crate::error::ErrorCode;
+    use crate::pow::Algorithm;
+    use crate::storage::{MemoryStorage, Storage};
+
+    const STUDENT: &str = "s1234567";
+
+    #[actix_rt::test]
+    async fn admin_routes_are_off_without_a_token() {
+        let app = ApplicationData::begin(Arc::new(MemoryStorage::new()));
+        let mut service = test::init_service(App::new().app_data(web::Data::new(app)).service(scope())).await;
+        let request = test::TestRequest::delete().uri("/admin/best").to_request();
+        let response = test::call_service(&mut service, request).await;
+        assert_eq!(response.status(), 403);
+    }
+
+    #[test]
+    fn bans_lock_the_student_out_until_lifted() {
+        let storage = Arc::new(MemoryStorage::new());
+        let mut credentials = Credentials::default();
+        let (key, _) = credentials.issue(STUDENT);
+        storage.save_credentials(&credentials).unwrap();
+        let app = ApplicationData::begin(storage.clone());
+        let request = test::TestRequest::default().header(API_KEY_HEADER, key.as_str()).to_http_request();
+
+        app.ban(STUDENT, "sharing keys").unwrap();
+        assert_eq!(app.authenticate(&request, STUDENT).unwrap_err().code, ErrorCode::Banned);
+        assert_eq!(storage.load_bans().unwrap().get(STUDENT).unwrap().reason, "sharing keys");
+
+        assert!(app.unban(STUDENT).unwrap());
+        assert!(app.authenticate(&request, STUDENT).is_ok());
+        assert!(!app.unban(STUDENT).unwrap());
+        assert!(storage.load_bans().unwrap().get(STUDENT).is_none());
+    }
+
+    #[test]
+    fn credentials_are_issued_and_reloaded_while_running() {
+        let storage = Arc::new(MemoryStorage::new());
+        let app = ApplicationData::begin(storage.clone());
+        let with_key = |key: &str| test::TestRequest::default().header(API_KEY_HEADER, key).to_http_request();
+
+        let (key, _) = app.issue_credentials(STUDENT).unwrap();
+        assert!(app.authenticate(&with_key(&key), STUDENT).is_ok());
+        assert!(storage.load_credentials().unwrap().check(STUDENT, &key).is_ok());
+
+        // As `--issue-key` does beside a running server.
+        let mut credentials = storage.load_credentials().unwrap();
+        let (new_key, _) = credentials.issue(STUDENT);
+        storage.save_credentials(&credentials).unwrap();
+        assert!(app.authenticate(&with_key(&new_key), STUDENT).is_err());
+        assert!(app.reload_credentials());
+        assert!(app.authenticate(&with_key(&new_key), STUDENT).is_ok());
+        assert_eq!(app.authenticate(&with_key(&key), STUDENT).unwrap_err().code, ErrorCode::UnknownApiKey);
+    }
+
+    #[test]
+    fn submitters_are_requeued_and_deleted() {
+        let storage = Arc::new(MemoryStorage::new());
+        let app = ApplicationData::begin(storage.clone());
+        {
+            let shared = app.submitter_from(STUDENT);
+            let mut submitter = shared.lock();
+            submitter.next_job("m", "challenge", Algorithm::Sha256);
+            submitter.next_job("m", "challenge", Algorithm::Sha256);
+            assert_eq!(submitter.requeue_pending(), 2);
+            assert!(submitter.pending_jobs.is_empty());
+            assert_eq!(submitter.unfinished_jobs.len(), 2);
+            app.save_submitter(&submitter).unwrap();
+        }
+        assert_eq!(storage.load_submitters().len(), 1);
+
+        assert!(app.delete_submitter(STUDENT).unwrap());
+        assert!(app.get_submitter(STUDENT).is_none());
+        assert!(storage.load_submitters().is_empty());
+        assert!(!app.delete_submitter(STUDENT).unwrap());
+    }
+
+    #[test]
+    fn requests_in_flight_do_not_bring_a_deleted_submitter_back() {
+        let storage = Arc::new(MemoryStorage::new());
+        let app = ApplicationData::begin(storage.clone());
+        // A request that looked the submitter up before the delete, and gets its lock after.
+        let in_flight = app.submitter_from(STUDENT);
+        app.save_submitter(&in_flight.lock()).unwrap();
+        assert!(app.delete_submitter(STUDENT).unwrap());
+        {
+            let mut submitter = in_flight.lock();
+            submitter.next_job("m", "challenge", Algorithm::Sha256);
+            app.save_submitter(&submitter).unwrap();
+        }
+        assert!(storage.load_submitters().is_empty());
+        assert!(!app.delete_submitter(STUDENT).unwrap());
+
+        // Later requests start over.
+        let fresh = app.submitter_from(STUDENT);
+        assert!(!fresh.lock().deleted);
+        assert!(fresh.lock().pending_jobs.is_empty());
+    }
+}
@@ -1,13 +1,26 @@
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use actix_web::HttpRequest;
+use parking_lot::{Mutex, RwLock};
 use serde::Deserialize;
 use serde::Serialize;
-use crate::submitter::Submitter;
-use crate::file_operations::{
-    get_cwd,
-    open_append_file,
-    open_overwrite_file,
-    open_read_file,
-};
+use crate::submitter::{Liveness, Submitter};
+use crate::hash_store::{Hash, HashStore};
+use crate::storage::Storage;
+use crate::competition::{Competition, RoundState};
+use crate::credentials::Credentials;
+use crate::error::{ApiError, ErrorCode};
+use crate::bans::BanList;
+use crate::rate_limit::RateLimiter;
+use crate::metrics::PoolMetrics;
+use crate::config::ChallengeScope;
+use crate::pow::Algorithm;
+use crate::hashrate::SubmitterHashrate;
+use crate::timeseries::{Metrics, Resolution, Sample, Series, TimeSeries};
+use crate::packets::RoundPacket;
+use crate::config::RewardSchemeKind;
+use crate::rewards::{Pplns, Proportional, RewardLedger, RewardRound, RewardScheme, RoundCloseReason};
 
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -19,10 +32,43 @@ pub struct BestSolution {
     pub hash: String,
 }
 
+pub type SharedSubmitter = Arc<Mutex<Submitter>>;
+
+/// State shared by every request.
+///
+/// Each submitter has its own lock, so requests from different students never wait on each
+/// other. The best solution, the hash set, the reward ledger and the competition rounds have
+/// their own locks too, as do the ban list, the API keys and the time series. Never take a submitter lock while holding one of
+/// those, and never hold two of those at once. The flush locks of the ledger and the rounds
+/// are taken before the ledger's or the competition's own lock, never while holding them.
 #[derive(Debug)]
 pub struct ApplicationData {
-    pub submitters: HashMap<String, Submitter>,
-    pub best: Option<BestSolution>,
+    submitters: RwLock<HashMap<String, SharedSubmitter>>,
+    best: Mutex<Option<BestSolution>>,
+    hashes: HashStore,
+    rewards: Mutex<RewardLedger>,
+    /// Set when the ledger has changed since it was last written, see `flush_rewards`.
+    rewards_dirty: AtomicBool,
+    /// Held while the ledger is written so writes land in order.
+    rewards_flush: Mutex<()>,
+    reward_scheme: Box<dyn RewardScheme>,
+    competition: Mutex<Competition>,
+    /// Set when the active round has changed since it was last written, see `flush_rounds`.
+    rounds_dirty: AtomicBool,
+    /// Held while rounds are written so writes land in order.
+    rounds_flush: Mutex<()>,
+    /// Challenge for every job when `challenge_scope` is `round` and no rounds are configured.
+    pool_challenge: String,
+    /// Issued API keys, replaced by `issue_credentials` and `reload_credentials`.
+    credentials: RwLock<Credentials>,
+    bans: Mutex<BanList>,
+    timeseries: Mutex<TimeSeries>,
+    /// Limits requests from each remote IP, see `rate_limit::RateLimit`.
+    pub ip_limiter: RateLimiter,
+    /// Limits job requests from each machine, keyed by `<student number>/<machine name>`.
+    pub job_limiter: RateLimiter,
+    pub metrics: PoolMetrics,
+    pub storage: Arc<dyn Storage>,
 }
 
 #[derive(PartialEq, Eq)]
@@ -32,70 +78,445 @@ pub enum HashSubmittion {
 }
 
 impl ApplicationData {
-    pub fn begin() -> Self {
-        let mut best = None;
-        let mut submitters = HashMap::new();
-        if let Ok(file) = open_read_file("best", "best.json") {
-            if let Ok(best_from_file) = serde_json::from_reader(&file) {
-                best = Some(best_from_file);
-            }
+    pub fn begin(storage: Arc<dyn Storage>) -> Self {
+        let config = crate::config::get();
+        let best = storage.load_best();
+        let submitters = storage.load_submitters().into_iter()
+            .map(|mut submitter| {
+                submitter.migrate();
+                (submitter.student_number.clone(), Arc::new(Mutex::new(submitter)))
+            })
+            .collect();
+        ApplicationData {
+            submitters: RwLock::new(submitters),
+            best: Mutex::new(best),
+            hashes: HashStore::open(Arc::clone(&storage)),
+            rewards: Mutex::new(storage.load_rewards().unwrap_or_default()),
+            rewards_dirty: AtomicBool::new(false),
+            rewards_flush: Mutex::new(()),
+            reward_scheme: match crate::config::get().reward_scheme {
+                RewardSchemeKind::Proportional => Box::new(Proportional),
+                RewardSchemeKind::Pplns => Box::new(Pplns { window: crate::config::get().pplns_window }),
+            },
+            competition: Mutex::new(Competition::new(
+                &crate::config::get().rounds,
+                |name| storage.load_round(name),
+            )),
+            rounds_dirty: AtomicBool::new(false),
+            rounds_flush: Mutex::new(()),
+            pool_challenge: crate::util::random_challenge(),
+            credentials: RwLock::new(storage.load_credentials().unwrap_or_default()),
+            bans: Mutex::new(storage.load_bans().unwrap_or_default()),
+            timeseries: Mutex::new(TimeSeries::load(&*storage, crate::util::get_time())),
+            ip_limiter: RateLimiter::new(config.ip_rate_limit, config.ip_rate_burst),
+            job_limiter: RateLimiter::new(config.job_rate_limit, config.job_rate_burst),
+            metrics: PoolMetrics::default(),
+            storage,
+        }
+    }
+
+    /// Checks the request carries the API key of `student_number`, if keys are required, and
+    /// that the student is not banned.
+    pub fn authenticate(&self, req: &HttpRequest, student_number: &str) -> Result<(), ApiError> {
+        if crate::config::get().require_api_key {
+            self.credentials.read().authenticate(req, student_number)?;
+        }
+        self.check_not_banned(student_number)
+    }
+
+    /// Checks `student_number` is not banned. Routes open to anyone call this on the student
+    /// they report on rather than on the caller, so a ban also hides a student's numbers from
+    /// everyone.
+    pub fn check_not_banned(&self, student_number: &str) -> Result<(), ApiError> {
+        if self.bans.lock().get(student_number).is_some() {
+            return Err(ErrorCode::Banned.into());
+        }
+        Ok(())
+    }
+
+    /// Checks the request carries the admin token.
+    pub fn authenticate_admin(&self, req: &HttpRequest) -> Result<(), ApiError> {
+        crate::credentials::authenticate_admin(req, &crate::config::get().admin_token)
+    }
+
+    /// Checks `body` was signed with the secret of `student_number`, if signatures are required.
+    pub fn verify_signature(&self, req: &HttpRequest, student_number: &str, body: &[u8]) -> Result<(), ApiError> {
+        if !crate::config::get().require_signature {
+            return Ok(());
         }
-        let str_path = format!("{}/data/submitters", get_cwd());
-        if let Ok(paths) = std::fs::read_dir(&str_path) {
-            for path in  paths {
-                let path = path.unwrap().path();
-                let str_path = format!("{}", path.display());
-                let student_number = str_path.split("/").last().unwrap();
-                if let Ok(file) = open_read_file(
-                    &format!("submitters/{}", student_number),
-                    "info.json"
-                ) {
-                    if let Ok(submitter) = serde_json::from_reader(file) {
-                        submitters.insert(String::from(student_number), submitter);
-                    }
-                }
+        self.credentials.read().verify_signature(req, student_number, body)
+    }
 
+    /// Issues a new API key and signing secret for `student_number` and saves them. Returns
+    /// `(key, secret)`. The old ones keep working if the save fails.
+    pub fn issue_credentials(&self, student_number: &str) -> std::io::Result<(String, String)> {
+        let mut credentials = self.credentials.write();
+        let mut issued = credentials.clone();
+        let key_and_secret = issued.issue(student_number);
+        self.storage.save_credentials(&issued)?;
+        *credentials = issued;
+        Ok(key_and_secret)
+    }
+
+    /// Replaces the API keys with those in storage, such as ones issued with `--issue-key`.
+    /// Returns false, keeping the current keys, if none could be loaded.
+    pub fn reload_credentials(&self) -> bool {
+        match self.storage.load_credentials() {
+            Some(credentials) => {
+                *self.credentials.write() = credentials;
+                true
             }
+            None => false,
         }
-        ApplicationData {
-            submitters,
-            best,
+    }
+
+    /// Returns the submitter with the given student number. If none exists, a new one is made.
+    pub fn submitter_from(&self, student_number: &str) -> SharedSubmitter {
+        if let Some(submitter) = self.submitters.read().get(student_number) {
+            return Arc::clone(submitter);
+        }
+        let mut submitters = self.submitters.write();
+        let submitter = submitters.entry(String::from(student_number))
+            .or_insert_with(|| Arc::new(Mutex::new(Submitter::new(student_number))));
+        Arc::clone(submitter)
+    }
+
+    pub fn get_submitter(&self, student_number: &str) -> Option<SharedSubmitter> {
+        self.submitters.read().get(student_number).map(Arc::clone)
+    }
+
+    /// Every submitter, for walking over without holding the map lock. Locking each one waits
+    /// on any write to storage made under it, so walk them on the blocking thread pool.
+    pub fn all_submitters(&self) -> Vec<SharedSubmitter> {
+        self.submitters.read().values().map(Arc::clone).collect()
+    }
+
+    /// Writes the submitter to storage. Call while still holding its lock so writes land in
+    /// the same order as the changes. Deleted submitters are not written.
+    pub fn save_submitter(&self, submitter: &Submitter) -> std::io::Result<()> {
+        if submitter.deleted {
+            return Ok(());
+        }
+        self.storage.save_submitter(submitter)
+    }
+
+    pub fn submit_hash(&self, hash: &Hash) -> HashSubmittion {
+        if self.hashes.insert(hash) {
+            HashSubmittion::Accepted
+        } else {
+            HashSubmittion::AlreadyExists
+        }
+    }
+
+    /// Makes the hashes recorded so far durable. Call before saving the shares they earned.
+    pub fn sync_hashes(&self) -> std::io::Result<()> {
+        self.storage.sync_hashes()
+    }
+
+    pub fn best(&self) -> Option<BestSolution> {
+        self.best.lock().clone()
+    }
+
+    /// Makes `candidate` the best solution if it beats the current one, and saves it.
+    pub fn offer_best(&self, candidate: BestSolution) -> std::io::Result<bool> {
+        let mut best = self.best.lock();
+        let is_best = match &*best {
+            Some(current_best) => candidate.leading_zero_bit_length > current_best.leading_zero_bit_length,
+            None => true,
+        };
+        if is_best {
+            self.storage.save_best(Some(&candidate))?;
+            *best = Some(candidate);
+        }
+        Ok(is_best)
+    }
+
+    pub fn reward_scheme_name(&self) -> &'static str {
+        self.reward_scheme.name()
+    }
+
+    /// Copy of the reward ledger.
+    pub fn rewards(&self) -> RewardLedger {
+        self.rewards.lock().clone()
+    }
+
+    /// Counts an accepted share towards the reward round, closing the round first if its
+    /// interval has passed. The share is written with the ledger by the next `flush_rewards`,
+    /// a closed round straight away.
+    pub fn record_reward_share(&self, student_number: &str, work: f64) -> std::io::Result<()> {
+        let config = crate::config::get();
+        let now = crate::util::get_time();
+        let closed = {
+            let mut rewards = self.rewards.lock();
+            let closed = if rewards.interval_elapsed(config.reward_round_interval, now) {
+                self.close_locked_round(&mut rewards, RoundCloseReason::Interval, now)?
+            } else {
+                None
+            };
+            rewards.record_share(student_number, work, config.pplns_window);
+            self.rewards_dirty.store(true, Ordering::SeqCst);
+            closed
+        };
+        if closed.is_some() {
+            self.flush_rewards()?;
+        }
+        Ok(())
+    }
+
+    /// Closes the current reward round, pays it out and saves the ledger.
+    pub fn close_reward_round(&self, reason: RoundCloseReason) -> std::io::Result<Option<RewardRound>> {
+        let round = {
+            let mut rewards = self.rewards.lock();
+            let round = self.close_locked_round(&mut rewards, reason, crate::util::get_time())?;
+            self.rewards_dirty.store(true, Ordering::SeqCst);
+            round
+        };
+        self.flush_rewards()?;
+        Ok(round)
+    }
+
+    fn close_locked_round(
+        &self,
+        rewards: &mut RewardLedger,
+        reason: RoundCloseReason,
+        now: f64,
+    ) -> std::io::Result<Option<RewardRound>> {
+        let reward = crate::config::get().reward_per_round;
+        let round = rewards.close_round(self.reward_scheme.as_ref(), reward, reason, now);
+        if let Some(round) = &round {
+            self.storage.append_reward_round(round)?;
+        }
+        Ok(round)
+    }
+
+    /// Writes the ledger if it changed since it was last written. The ledger lock is only
+    /// held to copy it, so shares are not held up by the write.
+    pub fn flush_rewards(&self) -> std::io::Result<()> {
+        let _flushing = self.rewards_flush.lock();
+        if !self.rewards_dirty.swap(false, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let rewards = self.rewards.lock().clone();
+        self.storage.save_rewards(&rewards).inspect_err(|_| {
+            self.rewards_dirty.store(true, Ordering::SeqCst);
+        })
+    }
+
+    pub fn save_rewards(&self) -> std::io::Result<()> {
+        self.rewards_dirty.store(true, Ordering::SeqCst);
+        self.flush_rewards()
+    }
+
+    /// Writes out what is only saved every `save_interval`, see `Config::save_interval`.
+    pub fn flush(&self) -> std::io::Result<()> {
+        self.archive_ended_rounds()?;
+        self.flush_rounds()?;
+        self.flush_rewards()
+    }
+
+    /// The challenge for a job handed out now, see `Config::challenge_scope`.
+    pub fn next_challenge(&self) -> String {
+        match crate::config::get().challenge_scope {
+            ChallengeScope::Job => crate::util::random_challenge(),
+            ChallengeScope::Round => match self.competition.lock().active(crate::util::get_time()) {
+                Some(round) => round.challenge.clone(),
+                None => self.pool_challenge.clone(),
+            },
+        }
+    }
+
+    /// The hash a job handed out now is checked with.
+    pub fn current_algorithm(&self) -> Algorithm {
+        let competition = self.competition.lock();
+        competition.active(crate::util::get_time())
+            .and_then(|round| round.algorithm)
+            .unwrap_or(crate::config::get().algorithm)
+    }
+
+    /// True if a competition round is active, or no rounds are configured.
+    pub fn round_is_open(&self) -> bool {
+        self.competition.lock().is_open(crate::util::get_time())
+    }
+
+    /// Counts an accepted share, and its solution, towards the active competition round. It
+    /// is written with the round by the next `flush_rounds`.
+    pub fn record_round_share(&self, student_number: &str, work: f64, solution: &BestSolution) {
+        let mut competition = self.competition.lock();
+        if let Some(round) = competition.active_mut(crate::util::get_time()) {
+            round.record_share(student_number, work);
+            round.offer_best(solution);
+            self.rounds_dirty.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Writes the active round if it changed since it was last written. Like
+    /// `flush_rewards`, only a copy is written.
+    pub fn flush_rounds(&self) -> std::io::Result<()> {
+        let _flushing = self.rounds_flush.lock();
+        if !self.rounds_dirty.swap(false, Ordering::SeqCst) {
+            return Ok(());
+        }
+        // A round that has ended since is written in full when it is archived.
+        let round = self.competition.lock().active(crate::util::get_time()).cloned();
+        match round {
+            Some(round) => self.storage.save_round(&round).inspect_err(|_| {
+                self.rounds_dirty.store(true, Ordering::SeqCst);
+            }),
+            None => Ok(()),
+        }
+    }
+
+    pub fn save_active_round(&self) -> std::io::Result<()> {
+        self.rounds_dirty.store(true, Ordering::SeqCst);
+        self.flush_rounds()
+    }
+
+    /// Writes out the results of rounds that have ended since the last call.
+    pub fn archive_ended_rounds(&self) -> std::io::Result<()> {
+        let now = crate::util::get_time();
+        // Checked first so job requests do not wait on a round being flushed.
+        if self.competition.lock().unarchived_ended(now).next().is_none() {
+            return Ok(());
+        }
+        // So an older copy from `flush_rounds` can not land over the archived round.
+        let _flushing = self.rounds_flush.lock();
+        let mut competition = self.competition.lock();
+        for round in competition.unarchived_ended(now) {
+            self.storage.save_round_results(&RoundPacket::from_round(round, now))?;
+            round.archived = true;
+            self.storage.save_round(round)?;
+        }
+        Ok(())
+    }
+
+    pub fn round(&self, name: &str) -> Option<RoundState> {
+        self.competition.lock().get(name).cloned()
+    }
+
+    /// Copy of every configured competition round.
+    pub fn rounds(&self) -> Vec<RoundState> {
+        self.competition.lock().rounds.clone()
+    }
+
+    /// Copy of the ban list.
+    pub fn bans(&self) -> BanList {
+        self.bans.lock().clone()
+    }
+
+    pub fn ban(&self, student_number: &str, reason: &str) -> std::io::Result<()> {
+        let mut bans = self.bans.lock();
+        bans.ban(student_number, reason, crate::util::get_time());
+        self.storage.save_bans(&bans)
+    }
+
+    /// Lifts a ban. Returns false if the student was not banned.
+    ///
+    /// The student's invalid solutions so far are forgiven, so they are not banned again by
+    /// their next submission.
+    pub fn unban(&self, student_number: &str) -> std::io::Result<bool> {
+        let shared = self.get_submitter(student_number);
+        let mut submitter = shared.as_ref().map(|shared| shared.lock());
+        let mut bans = self.bans.lock();
+        if !bans.unban(student_number) {
+            return Ok(false);
+        }
+        self.storage.save_bans(&bans)?;
+        drop(bans);
+        if let Some(submitter) = submitter.as_mut() {
+            submitter.invalid_shares_forgiven = submitter.invalid_shares_count;
+            self.save_submitter(submitter)?;
+        }
+        Ok(true)
+    }
+
+    /// Forgets a submitter and deletes it from storage. Returns false if there was none.
+    ///
+    /// Requests already waiting on the submitter's lock find it marked deleted and do not
+    /// write it back. Later requests start a new submitter.
+    pub fn delete_submitter(&self, student_number: &str) -> std::io::Result<bool> {
+        let shared = match self.get_submitter(student_number) {
+            Some(shared) => shared,
+            None => return Ok(false),
+        };
+        let mut submitter = shared.lock();
+        if submitter.deleted {
+            return Ok(false);
         }
+        submitter.deleted = true;
+        self.submitters.write().remove(student_number);
+        self.storage.delete_submitter(student_number)?;
+        Ok(true)
+    }
+
+    pub fn clear_best(&self) -> std::io::Result<()> {
+        let mut best = self.best.lock();
+        self.storage.save_best(None)?;
+        *best = None;
+        Ok(())
     }
 
-    pub fn submitter_from<'a>(&'a mut self, student_number: &str) -> &'a mut Submitter {
-        if let None = self.submitters.get(student_number) {
-            let submitter = Submitter::new(student_number);
-            self.submitters.insert(String::from(student_number), submitter);
+    /// Writes everything held in memory to storage.
+    pub fn save_all(&self) -> std::io::Result<()> {
+        for shared in self.all_submitters() {
+            self.save_submitter(&shared.lock())?;
         }
-        self.submitters.get_mut(student_number).unwrap()
+        self.storage.save_best(self.best.lock().as_ref())?;
+        self.save_rewards()?;
+        self.save_active_round()?;
+        self.storage.save_bans(&self.bans.lock())?;
+        self.hashes.compact();
+        Ok(())
     }
 
-    pub fn submit_hash(&mut self, hash: &String) -> HashSubmittion {
-        use std::io::BufRead;
-        use std::io::prelude::*;
-        // below line may fail if first time.
-        if let Ok(file) = open_read_file("hashes", "hashes.txt") {
-            for line in std::io::BufReader::new(file).lines() {
-                if let Ok(line) = line {
-                    if line.eq(hash) {
-                        return HashSubmittion::AlreadyExists 
-                    }
-                }
+    /// Takes back the jobs of every machine that has gone offline so others can have them.
+    /// Returns how many jobs were reclaimed.
+    pub fn reclaim_offline_jobs(&self) -> std::io::Result<usize> {
+        let now = crate::util::get_time();
+        let mut total = 0;
+        for shared in self.all_submitters() {
+            let mut submitter = shared.lock();
+            let reclaimed = submitter.reclaim_offline(now);
+            if reclaimed > 0 {
+                eprintln!("reclaimed {} jobs from offline machines of {}", reclaimed, submitter.student_number);
+                self.save_submitter(&submitter)?;
+                total += reclaimed;
             }
         }
-        let mut file = open_append_file("hashes", "hashes.txt")
-            .expect("Could not open data/hashes.txt for writing");
-        let _ = writeln!(file, "{}", hash);
-        HashSubmittion::Accepted
+        Ok(total)
     }
 
+    /// Samples the pool and every submitter into the time series.
+    pub fn record_sample(&self) -> std::io::Result<()> {
+        let now = crate::util::get_time();
+        let mut sample = Sample {
+            time: now,
+            pool: Metrics::default(),
+            submitters: Default::default(),
+        };
+        for shared in self.all_submitters() {
+            let submitter = shared.lock();
+            let hashrate = SubmitterHashrate::of(&submitter, now);
+            let metrics = Metrics {
+                reported_hashrate: hashrate.reported,
+                effective_hashrate: hashrate.effective.last_5m,
+                shares: submitter.accepted_shares_count,
+                work: submitter.work,
+                active_machines: hashrate.machines.iter()
+                    .filter(|machine| machine.liveness != Liveness::Offline)
+                    .count() as u64,
+            };
+            sample.pool.reported_hashrate += metrics.reported_hashrate;
+            sample.pool.effective_hashrate += metrics.effective_hashrate;
+            sample.pool.shares += metrics.shares;
+            sample.pool.work += metrics.work;
+            sample.pool.active_machines += metrics.active_machines;
+            sample.submitters.insert(submitter.student_number.clone(), metrics);
+        }
+        self.timeseries.lock().record(&*self.storage, sample)
+    }
 
-    pub fn save_best(&self, best: BestSolution) {
-        let file  = open_overwrite_file("best","best.json",
-            ).expect("Could not open/overwrite best solution file.");
-        serde_json::to_writer(&file, &best)
-            .expect("Counld not write JSON to best solution file");
+    /// `(time, metrics)` of `series` sampled in `[from, to]`.
+    pub fn history(&self, resolution: Resolution, series: Series, from: f64, to: f64) -> Vec<(f64, Metrics)> {
+        crate::timeseries::query(&*self.storage, resolution, series, from, to)
     }
 }
-
@@ -0,0 +1,38 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Ban {
+    /// Unix time the ban started.
+    pub since: f64,
+    pub reason: String,
+}
+
+/// Student numbers that may not use the pool.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(transparent)]
+pub struct BanList {
+    banned: HashMap<String, Ban>,
+}
+
+impl BanList {
+    /// Bans `student_number`, replacing the reason of an existing ban.
+    pub fn ban(&mut self, student_number: &str, reason: &str, now: f64) {
+        let ban = self.banned.entry(String::from(student_number)).or_insert(Ban {
+            since: now,
+            reason: String::new(),
+        });
+        ban.reason = String::from(reason);
+    }
+
+    /// Lifts the ban on `student_number`. Returns false if there was none.
+    pub fn unban(&mut self, student_number: &str) -> bool {
+        self.banned.remove(student_number).is_some()
+    }
+
+    pub fn get(&self, student_number: &str) -> Option<&Ban> {
+        self.banned.get(student_number)
+    }
+}
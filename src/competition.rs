@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::app::BestSolution;
+use crate::pow::Algorithm;
+
+/// A competition round as configured: a name and the window it accepts submissions in.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RoundSchedule {
+    pub name: String,
+    /// Unix time the round opens.
+    pub start: f64,
+    /// Unix time the round closes.
+    pub end: f64,
+    /// Hash used during the round instead of `Config::algorithm`.
+    #[serde(default)]
+    pub algorithm: Option<Algorithm>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum RoundStatus {
+    Upcoming,
+    Active,
+    Ended,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RoundEntry {
+    pub shares: u64,
+    pub work: f64,
+}
+
+/// Everything a competition round has collected so far.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RoundState {
+    pub name: String,
+    pub start: f64,
+    pub end: f64,
+    pub best: Option<BestSolution>,
+    pub entries: HashMap<String, RoundEntry>,
+    #[serde(default)]
+    pub algorithm: Option<Algorithm>,
+    /// Challenge shared by the round's jobs when `challenge_scope` is `round`.
+    #[serde(default = "crate::util::random_challenge")]
+    pub challenge: String,
+    /// Set once the round has ended and its results were written out.
+    pub archived: bool,
+}
+
+impl RoundState {
+    pub fn new(schedule: &RoundSchedule) -> Self {
+        RoundState {
+            name: schedule.name.clone(),
+            start: schedule.start,
+            end: schedule.end,
+            best: None,
+            entries: HashMap::new(),
+            algorithm: schedule.algorithm,
+            challenge: crate::util::random_challenge(),
+            archived: false,
+        }
+    }
+
+    pub fn status(&self, now: f64) -> RoundStatus {
+        if now < self.start {
+            RoundStatus::Upcoming
+        } else if now < self.end {
+            RoundStatus::Active
+        } else {
+            RoundStatus::Ended
+        }
+    }
+
+    pub fn record_share(&mut self, student_number: &str, work: f64) {
+        let entry = self.entries.entry(String::from(student_number)).or_default();
+        entry.shares += 1;
+        entry.work += work;
+    }
+
+    /// Makes `candidate` the round's best solution if it beats the current one.
+    pub fn offer_best(&mut self, candidate: &BestSolution) -> bool {
+        let is_best = match &self.best {
+            Some(best) => candidate.leading_zero_bit_length > best.leading_zero_bit_length,
+            None => true,
+        };
+        if is_best {
+            self.best = Some(candidate.clone());
+        }
+        is_best
+    }
+
+    /// Entries sorted by work, most first.
+    pub fn leaderboard(&self) -> Vec<(String, RoundEntry)> {
+        let mut entries: Vec<(String, RoundEntry)> = self.entries.iter()
+            .map(|(student_number, entry)| (student_number.clone(), entry.clone()))
+            .collect();
+        entries.sort_by(|a, b| b.1.work.total_cmp(&a.1.work));
+        entries
+    }
+}
+
+/// The configured competition rounds.
+///
+/// With no rounds configured the pool is always open and nothing is tracked per round.
+#[derive(Debug)]
+pub struct Competition {
+    pub rounds: Vec<RoundState>,
+}
+
+impl Competition {
+    /// Builds the rounds from their schedules, picking up saved state where there is some.
+    pub fn new(schedules: &[RoundSchedule], saved: impl Fn(&str) -> Option<RoundState>) -> Self {
+        let mut rounds: Vec<RoundState> = schedules.iter()
+            .map(|schedule| {
+                let mut round = saved(&schedule.name).unwrap_or_else(|| RoundState::new(schedule));
+                // The schedule in the config wins over the saved one.
+                round.start = schedule.start;
+                round.end = schedule.end;
+                round.algorithm = schedule.algorithm;
+                round
+            })
+            .collect();
+        rounds.sort_by(|a, b| a.start.total_cmp(&b.start));
+        Competition { rounds }
+    }
+
+    /// True if submissions are accepted at `now`.
+    pub fn is_open(&self, now: f64) -> bool {
+        self.rounds.is_empty() || self.active(now).is_some()
+    }
+
+    pub fn active(&self, now: f64) -> Option<&RoundState> {
+        self.rounds.iter().find(|round| round.status(now) == RoundStatus::Active)
+    }
+
+    pub fn active_mut(&mut self, now: f64) -> Option<&mut RoundState> {
+        self.rounds.iter_mut().find(|round| round.status(now) == RoundStatus::Active)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&RoundState> {
+        self.rounds.iter().find(|round| round.name == name)
+    }
+
+    /// Ended rounds whose results have not been archived yet.
+    pub fn unarchived_ended(&mut self, now: f64) -> impl Iterator<Item = &mut RoundState> {
+        self.rounds.iter_mut()
+            .filter(move |round| !round.archived && round.status(now) == RoundStatus::Ended)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(name: &str, start: f64, end: f64) -> RoundSchedule {
+        RoundSchedule { name: String::from(name), start, end, algorithm: None }
+    }
+
+    #[test]
+    fn rounds_open_and_close_on_schedule() {
+        let competition = Competition::new(
+            &[schedule("second", 200.0, 300.0), schedule("first", 100.0, 200.0)],
+            |_| None,
+        );
+        assert_eq!(competition.rounds[0].name, "first");
+        assert!(!competition.is_open(50.0));
+        assert_eq!(competition.active(100.0).unwrap().name, "first");
+        assert_eq!(competition.active(200.0).unwrap().name, "second");
+        assert!(competition.is_open(299.0));
+        assert!(!competition.is_open(300.0));
+        assert_eq!(competition.rounds[0].status(50.0), RoundStatus::Upcoming);
+        assert_eq!(competition.rounds[0].status(250.0), RoundStatus::Ended);
+
+        assert!(Competition::new(&[], |_| None).is_open(0.0));
+    }
+
+    #[test]
+    fn ended_rounds_are_archived_once() {
+        let mut competition = Competition::new(
+            &[schedule("first", 100.0, 200.0), schedule("second", 200.0, 300.0)],
+            |_| None,
+        );
+        competition.active_mut(150.0).unwrap().record_share("s1", 2.0);
+
+        let ended: Vec<String> = competition.unarchived_ended(250.0).map(|round| round.name.clone()).collect();
+        assert_eq!(ended, vec!["first"]);
+        for round in competition.unarchived_ended(250.0) {
+            round.archived = true;
+        }
+        assert_eq!(competition.unarchived_ended(250.0).count(), 0);
+        assert_eq!(competition.unarchived_ended(350.0).count(), 1);
+
+        // After a restart the saved state is picked up, but the configured schedule wins.
+        let saved = competition.get("first").cloned().unwrap();
+        let restarted = Competition::new(&[schedule("first", 100.0, 400.0)], |name| {
+            if name == "first" { Some(saved.clone()) } else { None }
+        });
+        let first = restarted.get("first").unwrap();
+        assert!(first.archived);
+        assert_eq!(first.end, 400.0);
+        assert_eq!(first.entries["s1"].shares, 1);
+    }
+
+    #[test]
+    fn leaderboard_orders_by_work() {
+        let mut round = RoundState::new(&schedule("first", 0.0, 1.0));
+        round.record_share("s1", 1.0);
+        round.record_share("s2", 4.0);
+        round.record_share("s1", 1.0);
+        let leaderboard = round.leaderboard();
+        assert_eq!(leaderboard[0].0, "s2");
+        assert_eq!(leaderboard[1].1.shares, 2);
+
+        let best = |bits| BestSolution {
+            student_number: String::from("s1"),
+            job_number: 0,
+            leading_zero_bit_length: bits,
+            nounce: String::from("0"),
+            hash: String::new(),
+        };
+        assert!(round.offer_best(&best(40)));
+        assert!(!round.offer_best(&best(40)));
+        assert!(round.offer_best(&best(41)));
+    }
+}
@@ -0,0 +1,564 @@
+use std::net::ToSocketAddrs;
+use std::sync::OnceLock;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::competition::RoundSchedule;
+use crate::pow::Algorithm;
+
+/// Config file read when `--config` is not given. It is fine for it not to exist.
+const DEFAULT_CONFIG_FILE: &str = "hasher_agg.toml";
+
+/// Prefix of the environment variables that override config file values.
+const ENV_PREFIX: &str = "HASHER_AGG_";
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Server settings.
+///
+/// Values are layered: built in defaults, then the TOML config file, then `HASHER_AGG_*`
+/// environment variables, then command line flags.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Address the HTTP server listens on.
+    pub bind_address: String,
+    /// Directory all pool data is kept in. Relative paths are relative to the cwd.
+    pub data_dir: String,
+    /// Fewest leading zero bits a hash needs to count as a share.
+    pub min_zero_bits: u8,
+    /// Most leading zero bits vardiff will ask of a machine.
+    pub max_zero_bits: u8,
+    /// Seconds between shares vardiff aims for on each machine.
+    pub target_share_interval: f64,
+    /// Seconds of shares vardiff looks at before retargeting a machine.
+    pub vardiff_window: f64,
+    /// Seconds a job may stay pending before it is handed to someone else.
+    pub job_lease_timeout: f64,
+    /// Seconds of work a job should take a machine.
+    pub target_job_duration: f64,
+    /// Most nounces a job may cover, however fast the machine says it is.
+    pub max_job_size: u64,
+    /// Number of reported hashrates each machine averages over.
+    pub history_window: usize,
+    /// `env_logger` filter string.
+    pub log_level: String,
+    /// Where pool data is persisted.
+    pub storage: StorageBackend,
+    /// How each reward round is split between submitters.
+    pub reward_scheme: RewardSchemeKind,
+    /// Number of most recent shares the PPLNS scheme pays for.
+    pub pplns_window: usize,
+    /// Reward split between submitters each time a round closes.
+    pub reward_per_round: f64,
+    /// Close the reward round whenever a new best solution is found.
+    pub reward_close_on_best: bool,
+    /// Close the reward round after this many seconds. Zero disables.
+    pub reward_round_interval: f64,
+    /// Seconds between writes of state that changes with every share: the reward ledger and
+    /// the active competition round. They are written on shutdown too.
+    pub save_interval: f64,
+    /// Hash shares are checked with, unless the active competition round picks its own.
+    pub algorithm: Algorithm,
+    /// Whether mutating routes need the student's API key, see `--issue-key`.
+    pub require_api_key: bool,
+    /// Whether `/job/submit` bodies must be signed with the student's secret.
+    pub require_signature: bool,
+    /// Seconds a signed submission's timestamp may be off from the server's clock.
+    pub signature_max_age: f64,
+    /// A machine whose share of invalid solutions reaches this is quarantined. Duplicates do
+    /// not count. Zero turns quarantining off.
+    pub quarantine_invalid_ratio: f64,
+    /// Solutions a machine must have sent recently before it can be quarantined.
+    pub quarantine_window: u64,
+    /// A student with this many invalid solutions since their last unban, at least
+    /// `quarantine_invalid_ratio` of all they sent, is banned. Zero turns banning off.
+    pub ban_invalid_shares: u64,
+    /// Requests a second each remote IP may make, zero for no limit.
+    pub ip_rate_limit: f64,
+    /// Requests a remote IP may make at once before `ip_rate_limit` applies.
+    pub ip_rate_burst: f64,
+    /// Job requests a second each machine may make, zero for no limit.
+    pub job_rate_limit: f64,
+    /// Job requests a machine may make at once before `job_rate_limit` applies.
+    pub job_rate_burst: f64,
+    /// Jobs a machine may hold at once.
+    pub max_pending_jobs: usize,
+    /// Machines a student may have. Job rate and pending jobs are limited per machine, so
+    /// this bounds them per student.
+    pub max_machines: usize,
+    /// Seconds without a request before a machine shows as stale.
+    pub machine_stale_after: f64,
+    /// Seconds without a request before a machine is offline and its jobs are reclaimed.
+    pub machine_offline_after: f64,
+    /// Seconds between time series samples, zero turns sampling off.
+    pub timeseries_interval: f64,
+    /// Seconds raw time series samples are kept.
+    pub timeseries_raw_retention: f64,
+    /// Seconds hourly time series samples are kept.
+    pub timeseries_retention: f64,
+    /// Token the `/admin` routes need in an `X-Admin-Token` header. Empty turns them off.
+    pub admin_token: String,
+    /// How often the challenge mixed into every hashed preimage changes.
+    pub challenge_scope: ChallengeScope,
+    /// Competition rounds. Only settable in the config file, as `[[rounds]]` tables. With
+    /// none the pool always accepts submissions.
+    pub rounds: Vec<RoundSchedule>,
+    /// Set by `--issue-key`: issue a key to this student number instead of serving.
+    #[serde(skip)]
+    pub issue_key: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RewardSchemeKind {
+    Proportional,
+    Pplns,
+}
+
+impl std::str::FromStr for RewardSchemeKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "proportional" => Ok(RewardSchemeKind::Proportional),
+            "pplns" => Ok(RewardSchemeKind::Pplns),
+            _ => Err(String::from("expected 'proportional' or 'pplns'")),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChallengeScope {
+    /// Every job gets its own challenge.
+    Job,
+    /// Every job in a competition round shares the round's challenge. Without competition
+    /// rounds a new challenge is drawn each time the server starts.
+    Round,
+}
+
+impl std::str::FromStr for ChallengeScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "job" => Ok(ChallengeScope::Job),
+            "round" => Ok(ChallengeScope::Round),
+            _ => Err(String::from("expected 'job' or 'round'")),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// Files under `data_dir`.
+    Filesystem,
+    /// Memory only, everything is lost on exit. Handy for trying out clients.
+    Memory,
+}
+
+impl std::str::FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "filesystem" => Ok(StorageBackend::Filesystem),
+            "memory" => Ok(StorageBackend::Memory),
+            _ => Err(String::from("expected 'filesystem' or 'memory'")),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind_address: String::from("0.0.0.0:9876"),
+            data_dir: String::from("data"),
+            min_zero_bits: crate::constants::MINIMUN_ZERO_BIT_LENGTH,
+            max_zero_bits: 64,
+            target_share_interval: 30.0,
+            vardiff_window: 5.0 * 60.0,
+            job_lease_timeout: 10.0 * 60.0,
+            target_job_duration: 30.0,
+            max_job_size: 1_000_000_000_000,
+            history_window: 100,
+            log_level: String::from("actix_web=debug"),
+            storage: StorageBackend::Filesystem,
+            reward_scheme: RewardSchemeKind::Proportional,
+            pplns_window: 1000,
+            reward_per_round: 1.0,
+            reward_close_on_best: true,
+            reward_round_interval: 0.0,
+            save_interval: 5.0,
+            algorithm: Algorithm::Sha256,
+            require_api_key: true,
+            require_signature: true,
+            signature_max_age: 300.0,
+            quarantine_invalid_ratio: 0.5,
+            quarantine_window: 50,
+            ban_invalid_shares: 500,
+            ip_rate_limit: 50.0,
+            ip_rate_burst: 100.0,
+            job_rate_limit: 1.0,
+            job_rate_burst: 10.0,
+            max_pending_jobs: 10,
+            max_machines: 64,
+            machine_stale_after: 60.0,
+            machine_offline_after: 5.0 * 60.0,
+            timeseries_interval: 60.0,
+            timeseries_raw_retention: 2.0 * 24.0 * 60.0 * 60.0,
+            timeseries_retention: 365.0 * 24.0 * 60.0 * 60.0,
+            admin_token: String::new(),
+            challenge_scope: ChallengeScope::Job,
+            rounds: vec![],
+            issue_key: None,
+        }
+    }
+}
+
+const USAGE: &str = "\
+Usage: hasher_agg [OPTIONS]
+
+Options:
+    --config <FILE>                 TOML config file (default: hasher_agg.toml)
+    --bind-address <ADDR>           address to listen on (default: 0.0.0.0:9876)
+    --data-dir <DIR>                data directory (default: data)
+    --min-zero-bits <N>             minimum leading zero bits of a share (default: 34)
+    --max-zero-bits <N>             maximum difficulty vardiff may assign (default: 64)
+    --target-share-interval <SECS>  seconds between shares vardiff aims for (default: 30)
+    --vardiff-window <SECS>         seconds of shares per vardiff retarget (default: 300)
+    --job-lease-timeout <SECS>      seconds before a pending job is reissued (default: 600)
+    --target-job-duration <SECS>    seconds of work per job (default: 30)
+    --max-job-size <N>              most nounces per job (default: 1000000000000)
+    --history-window <N>            reported hashrates averaged per machine (default: 100)
+    --log-level <FILTER>            env_logger filter (default: actix_web=debug)
+    --storage <BACKEND>             filesystem or memory (default: filesystem)
+    --reward-scheme <SCHEME>        proportional or pplns (default: proportional)
+    --pplns-window <N>              shares paid for by pplns (default: 1000)
+    --reward-per-round <AMOUNT>     reward split each round (default: 1)
+    --reward-close-on-best <BOOL>   close the round on a new best solution (default: true)
+    --reward-round-interval <SECS>  close the round every SECS seconds, 0 for never (default: 0)
+    --save-interval <SECS>          seconds between writes of the reward ledger and the active
+                                    competition round (default: 5)
+    --algorithm <ALGORITHM>         sha256, sha256d, sha3-256, blake2s or blake3 (default: sha256)
+    --require-api-key <BOOL>        require an X-Api-Key header on mutating routes (default: true)
+    --issue-key <STUDENT_NUMBER>    issue a new API key and signing secret for a student, print
+                                    them and exit. Replaces any the student had. A running server
+                                    picks them up on POST /admin/credentials/reload
+    --require-signature <BOOL>      require /job/submit bodies to carry an X-Signature header,
+                                    the hex HMAC-SHA256 of the body keyed with the student's
+                                    secret (default: true)
+    --signature-max-age <SECS>      how far a signed submission's timestamp may be off (default: 300)
+    --quarantine-invalid-ratio <R>  quarantine a machine once its recent solutions are at least
+                                    this share invalid, 0 for never (default: 0.5)
+    --quarantine-window <N>         recent solutions a machine needs before the ratio counts
+                                    (default: 50)
+    --ban-invalid-shares <N>        ban a student once this many of their solutions since their
+                                    last unban were invalid, and at least the quarantine ratio of
+                                    all they sent, so new machine names do not dodge quarantine.
+                                    0 for never (default: 500)
+    --ip-rate-limit <N>             requests a second per remote IP, 0 for no limit (default: 50)
+    --ip-rate-burst <N>             requests a remote IP may make at once (default: 100)
+    --job-rate-limit <N>            job requests a second per machine, 0 for no limit (default: 1)
+    --job-rate-burst <N>            job requests a machine may make at once (default: 10)
+    --max-pending-jobs <N>          jobs a machine may hold at once (default: 10)
+    --max-machines <N>              machines a student may have (default: 64)
+    --machine-stale-after <SECS>    seconds without a request before a machine is stale
+                                    (default: 60)
+    --machine-offline-after <SECS>  seconds without a request before a machine is offline and its
+                                    jobs are handed to others (default: 300)
+    --timeseries-interval <SECS>    seconds between history samples, 0 for none (default: 60)
+    --timeseries-raw-retention <SECS>
+                                    seconds full resolution history is kept (default: 172800)
+    --timeseries-retention <SECS>   seconds hourly history is kept (default: 31536000)
+    --admin-token <TOKEN>           token for the /admin routes, empty disables them (default: empty)
+    --challenge-scope <SCOPE>       job or round, how often the hash challenge changes (default: job)
+    --help                          print this message
+
+Every option except --config and --issue-key may also be set with a HASHER_AGG_<OPTION> environment
+variable, e.g. HASHER_AGG_BIND_ADDRESS=127.0.0.1:9876.
+
+Competition rounds can only be set in the config file. Outside of every round the pool
+hands out no jobs; with no rounds it is always open.
+
+    [[rounds]]
+    name = \"week-1\"     # letters, digits, '-' and '_'
+    start = 1700000000  # unix time
+    end = 1700604800
+    algorithm = \"blake3\"  # optional, overrides --algorithm during the round
+";
+
+impl Config {
+    /// Builds the config from the config file, environment and command line arguments.
+    pub fn load() -> Result<Self, String> {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        let mut flags = Vec::new();
+        let mut config_file = None;
+        let mut issue_key = None;
+        let mut it = args.into_iter();
+        while let Some(arg) = it.next() {
+            if arg == "--help" || arg == "-h" {
+                print!("{}", USAGE);
+                std::process::exit(0);
+            }
+            let flag = arg.strip_prefix("--")
+                .ok_or_else(|| format!("unexpected argument '{}'\n\n{}", arg, USAGE))?;
+            let (key, value) = match flag.split_once('=') {
+                Some((key, value)) => (String::from(key), String::from(value)),
+                None => {
+                    let value = it.next()
+                        .ok_or_else(|| format!("missing value for '--{}'", flag))?;
+                    (String::from(flag), value)
+                }
+            };
+            if key == "config" {
+                config_file = Some(value);
+            } else if key == "issue-key" {
+                issue_key = Some(value);
+            } else {
+                flags.push((key.replace('-', "_"), value));
+            }
+        }
+
+        let mut config = match config_file {
+            Some(path) => Self::from_file(&path)?,
+            None if std::path::Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(DEFAULT_CONFIG_FILE)?
+            }
+            None => Config::default(),
+        };
+        for (key, value) in std::env::vars() {
+            if let Some(key) = key.strip_prefix(ENV_PREFIX) {
+                config.set(&key.to_lowercase(), &value)
+                    .map_err(|e| format!("{}{}: {}", ENV_PREFIX, key, e))?;
+            }
+        }
+        for (key, value) in flags {
+            config.set(&key, &value)
+                .map_err(|e| format!("--{}: {}", key.replace('_', "-"), e))?;
+        }
+        config.issue_key = issue_key;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("could not read config file {}: {}", path, e))?;
+        toml::from_str(&text)
+            .map_err(|e| format!("could not parse config file {}: {}", path, e))
+    }
+
+    /// Sets a single option from its string form.
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        fn parse<T: std::str::FromStr>(value: &str) -> Result<T, String>
+        where
+            T::Err: std::fmt::Display,
+        {
+            value.parse().map_err(|e| format!("invalid value '{}': {}", value, e))
+        }
+        match key {
+            "bind_address" => self.bind_address = String::from(value),
+            "data_dir" => self.data_dir = String::from(value),
+            "min_zero_bits" => self.min_zero_bits = parse(value)?,
+            "max_zero_bits" => self.max_zero_bits = parse(value)?,
+            "target_share_interval" => self.target_share_interval = parse(value)?,
+            "vardiff_window" => self.vardiff_window = parse(value)?,
+            "job_lease_timeout" => self.job_lease_timeout = parse(value)?,
+            "target_job_duration" => self.target_job_duration = parse(value)?,
+            "max_job_size" => self.max_job_size = parse(value)?,
+            "history_window" => self.history_window = parse(value)?,
+            "log_level" => self.log_level = String::from(value),
+            "storage" => self.storage = parse(value)?,
+            "reward_scheme" => self.reward_scheme = parse(value)?,
+            "pplns_window" => self.pplns_window = parse(value)?,
+            "reward_per_round" => self.reward_per_round = parse(value)?,
+            "reward_close_on_best" => self.reward_close_on_best = parse(value)?,
+            "reward_round_interval" => self.reward_round_interval = parse(value)?,
+            "save_interval" => self.save_interval = parse(value)?,
+            "algorithm" => self.algorithm = parse(value)?,
+            "require_api_key" => self.require_api_key = parse(value)?,
+            "require_signature" => self.require_signature = parse(value)?,
+            "signature_max_age" => self.signature_max_age = parse(value)?,
+            "quarantine_invalid_ratio" => self.quarantine_invalid_ratio = parse(value)?,
+            "quarantine_window" => self.quarantine_window = parse(value)?,
+            "ban_invalid_shares" => self.ban_invalid_shares = parse(value)?,
+            "ip_rate_limit" => self.ip_rate_limit = parse(value)?,
+            "ip_rate_burst" => self.ip_rate_burst = parse(value)?,
+            "job_rate_limit" => self.job_rate_limit = parse(value)?,
+            "job_rate_burst" => self.job_rate_burst = parse(value)?,
+            "max_pending_jobs" => self.max_pending_jobs = parse(value)?,
+            "max_machines" => self.max_machines = parse(value)?,
+            "machine_stale_after" => self.machine_stale_after = parse(value)?,
+            "machine_offline_after" => self.machine_offline_after = parse(value)?,
+            "timeseries_interval" => self.timeseries_interval = parse(value)?,
+            "timeseries_raw_retention" => self.timeseries_raw_retention = parse(value)?,
+            "timeseries_retention" => self.timeseries_retention = parse(value)?,
+            "admin_token" => self.admin_token = String::from(value),
+            "challenge_scope" => self.challenge_scope = parse(value)?,
+            _ => return Err(String::from("unknown option")),
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.bind_address.to_socket_addrs().is_err() {
+            return Err(format!("bind_address '{}' is not a valid address", self.bind_address));
+        }
+        if self.data_dir.is_empty() {
+            return Err(String::from("data_dir must not be empty"));
+        }
+        if self.signature_max_age <= 0.0 || !self.signature_max_age.is_finite() {
+            return Err(String::from("signature_max_age must be positive"));
+        }
+        if !(0.0..=1.0).contains(&self.quarantine_invalid_ratio) {
+            return Err(String::from("quarantine_invalid_ratio must be between 0 and 1"));
+        }
+        if self.quarantine_window == 0 {
+            return Err(String::from("quarantine_window must be at least 1"));
+        }
+        for (name, rate, burst) in [
+            ("ip", self.ip_rate_limit, self.ip_rate_burst),
+            ("job", self.job_rate_limit, self.job_rate_burst),
+        ] {
+            if rate < 0.0 || !rate.is_finite() {
+                return Err(format!("{}_rate_limit must not be negative", name));
+            }
+            if rate > 0.0 && (burst < 1.0 || !burst.is_finite()) {
+                return Err(format!("{}_rate_burst must be at least 1", name));
+            }
+        }
+        if self.save_interval <= 0.0 || !self.save_interval.is_finite() {
+            return Err(String::from("save_interval must be greater than zero"));
+        }
+        if self.max_pending_jobs == 0 {
+            return Err(String::from("max_pending_jobs must be at least 1"));
+        }
+        if self.max_machines == 0 {
+            return Err(String::from("max_machines must be at least 1"));
+        }
+        if self.machine_stale_after <= 0.0 || !self.machine_stale_after.is_finite() {
+            return Err(String::from("machine_stale_after must be greater than zero"));
+        }
+        if self.machine_offline_after < self.machine_stale_after || !self.machine_offline_after.is_finite() {
+            return Err(String::from("machine_offline_after must not be less than machine_stale_after"));
+        }
+        // Hourly samples are averaged from the raw samples of each hour.
+        if !(0.0..=3600.0).contains(&self.timeseries_interval) {
+            return Err(String::from("timeseries_interval must be between 0 and 3600"));
+        }
+        if self.timeseries_raw_retention < 3600.0 || !self.timeseries_raw_retention.is_finite() {
+            return Err(String::from("timeseries_raw_retention must be at least 3600"));
+        }
+        if self.timeseries_retention < self.timeseries_raw_retention || !self.timeseries_retention.is_finite() {
+            return Err(String::from("timeseries_retention must not be less than timeseries_raw_retention"));
+        }
+        if self.min_zero_bits == 0 {
+            return Err(String::from("min_zero_bits must be at least 1"));
+        }
+        if self.max_zero_bits < self.min_zero_bits {
+            return Err(String::from("max_zero_bits must not be less than min_zero_bits"));
+        }
+        if self.target_share_interval <= 0.0 || !self.target_share_interval.is_finite() {
+            return Err(String::from("target_share_interval must be greater than zero"));
+        }
+        if self.vardiff_window <= 0.0 || !self.vardiff_window.is_finite() {
+            return Err(String::from("vardiff_window must be greater than zero"));
+        }
+        if self.job_lease_timeout <= 0.0 || !self.job_lease_timeout.is_finite() {
+            return Err(String::from("job_lease_timeout must be greater than zero"));
+        }
+        if self.target_job_duration <= 0.0 || !self.target_job_duration.is_finite() {
+            return Err(String::from("target_job_duration must be greater than zero"));
+        }
+        if self.max_job_size < 1_000_000 {
+            return Err(String::from("max_job_size must be at least 1000000"));
+        }
+        if self.pplns_window == 0 {
+            return Err(String::from("pplns_window must be at least 1"));
+        }
+        if self.reward_per_round < 0.0 || !self.reward_per_round.is_finite() {
+            return Err(String::from("reward_per_round must not be negative"));
+        }
+        if self.reward_round_interval < 0.0 || !self.reward_round_interval.is_finite() {
+            return Err(String::from("reward_round_interval must not be negative"));
+        }
+        for (i, round) in self.rounds.iter().enumerate() {
+            let valid_name = !round.name.is_empty() && round.name.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid_name {
+                return Err(format!("round name '{}' may only use letters, digits, '-' and '_'", round.name));
+            }
+            if round.start >= round.end {
+                return Err(format!("round '{}' must start before it ends", round.name));
+            }
+            for other in self.rounds[..i].iter() {
+                if other.name == round.name {
+                    return Err(format!("round '{}' is configured twice", round.name));
+                }
+                if round.start < other.end && other.start < round.end {
+                    return Err(format!("rounds '{}' and '{}' overlap", other.name, round.name));
+                }
+            }
+        }
+        if self.history_window == 0 {
+            return Err(String::from("history_window must be at least 1"));
+        }
+        Ok(())
+    }
+}
+
+/// Makes `config` the config returned by `get`. Only the first call has any effect.
+pub fn init(config: Config) {
+    let _ = CONFIG.set(config);
+}
+
+/// The active config, or the defaults if `init` has not been called.
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_parses_options_by_name() {
+        let mut config = Config::default();
+        config.set("min_zero_bits", "20").unwrap();
+        config.set("data_dir", "/tmp/pool").unwrap();
+        assert_eq!(config.min_zero_bits, 20);
+        assert_eq!(config.data_dir, "/tmp/pool");
+        assert!(config.set("min_zero_bits", "many").is_err());
+        assert!(config.set("min_zero_bits", "256").is_err());
+        assert!(config.set("no_such_option", "1").is_err());
+    }
+
+    #[test]
+    fn config_file_only_overrides_what_it_sets() {
+        let config: Config = toml::from_str("min_zero_bits = 20\nlog_level = \"info\"").unwrap();
+        assert_eq!(config.min_zero_bits, 20);
+        assert_eq!(config.log_level, "info");
+        assert_eq!(config.bind_address, Config::default().bind_address);
+        assert!(toml::from_str::<Config>("no_such_option = 1").is_err());
+    }
+
+    #[test]
+    fn validate_rejects_values_the_pool_can_not_run_with() {
+        assert!(Config::default().validate().is_ok());
+        let invalid = [
+            Config { bind_address: String::from("not an address"), ..Config::default() },
+            Config { data_dir: String::new(), ..Config::default() },
+            Config { min_zero_bits: 0, ..Config::default() },
+            Config { job_lease_timeout: 0.0, ..Config::default() },
+            Config { target_job_duration: f64::NAN, ..Config::default() },
+            Config { history_window: 0, ..Config::default() },
+        ];
+        for config in invalid.iter() {
+            assert!(config.validate().is_err(), "{:?}", config);
+        }
+    }
+}
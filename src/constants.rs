@@ -1 +1,2 @@
+/// Default for `Config::min_zero_bits`.
 pub const MINIMUN_ZERO_BIT_LENGTH: u8 = 34;
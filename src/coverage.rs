@@ -0,0 +1,177 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+/// A set of nounces stored as sorted, disjoint, non-touching half open ranges `[start, end)`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct IntervalSet {
+    ranges: Vec<(u64, u64)>,
+}
+
+impl IntervalSet {
+    pub fn ranges(&self) -> &[(u64, u64)] {
+        &self.ranges
+    }
+
+    /// Number of nounces in the set.
+    pub fn total(&self) -> u64 {
+        self.ranges.iter().map(|(start, end)| end - start).sum()
+    }
+
+    pub fn insert(&mut self, start: u64, end: u64) {
+        if start >= end {
+            return;
+        }
+        // Ranges that overlap or touch [start, end) are merged into it.
+        let first = self.ranges.partition_point(|&(_, e)| e < start);
+        let last = self.ranges.partition_point(|&(s, _)| s <= end);
+        let mut merged = (start, end);
+        if first < last {
+            merged.0 = merged.0.min(self.ranges[first].0);
+            merged.1 = merged.1.max(self.ranges[last - 1].1);
+        }
+        self.ranges.splice(first..last, std::iter::once(merged));
+    }
+
+    pub fn remove(&mut self, start: u64, end: u64) {
+        if start >= end {
+            return;
+        }
+        let first = self.ranges.partition_point(|&(_, e)| e <= start);
+        let last = self.ranges.partition_point(|&(s, _)| s < end);
+        if first >= last {
+            return;
+        }
+        let mut kept = Vec::with_capacity(2);
+        let (head_start, _) = self.ranges[first];
+        let (_, tail_end) = self.ranges[last - 1];
+        if head_start < start {
+            kept.push((head_start, start));
+        }
+        if tail_end > end {
+            kept.push((end, tail_end));
+        }
+        self.ranges.splice(first..last, kept);
+    }
+
+    /// The parts of `[start, end)` that are not in the set.
+    pub fn complement(&self, start: u64, end: u64) -> IntervalSet {
+        let mut gaps = IntervalSet::default();
+        gaps.insert(start, end);
+        for &(s, e) in self.ranges.iter() {
+            gaps.remove(s, e);
+        }
+        gaps
+    }
+}
+
+/// Which parts of a submitter's nounce space have been handed out and searched.
+///
+/// Every nounce below `Submitter::next_nounce` should be in exactly one of the three sets.
+/// Anything that is not (see `gaps`) was lost, e.g. leased just before a crash.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Coverage {
+    /// Searched and returned by a client.
+    pub completed: IntervalSet,
+    /// Handed to a client and not yet returned.
+    pub leased: IntervalSet,
+    /// Handed out but not searched; waiting in `unfinished_jobs` to be handed out again.
+    pub abandoned: IntervalSet,
+}
+
+impl Coverage {
+    pub fn lease(&mut self, start: u64, end: u64) {
+        self.abandoned.remove(start, end);
+        self.leased.insert(start, end);
+    }
+
+    pub fn complete(&mut self, start: u64, end: u64) {
+        self.leased.remove(start, end);
+        self.abandoned.remove(start, end);
+        self.completed.insert(start, end);
+    }
+
+    pub fn abandon(&mut self, start: u64, end: u64) {
+        self.leased.remove(start, end);
+        for (s, e) in self.completed.complement(start, end).ranges() {
+            self.abandoned.insert(*s, *e);
+        }
+    }
+
+    /// Ranges below `next_nounce` that are in none of the sets.
+    pub fn gaps(&self, next_nounce: u64) -> IntervalSet {
+        let mut gaps = self.completed.complement(0, next_nounce);
+        for set in [&self.leased, &self.abandoned] {
+            for &(s, e) in set.ranges() {
+                gaps.remove(s, e);
+            }
+        }
+        gaps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(ranges: &[(u64, u64)]) -> IntervalSet {
+        let mut set = IntervalSet::default();
+        for &(start, end) in ranges {
+            set.insert(start, end);
+        }
+        set
+    }
+
+    #[test]
+    fn insert_merges_overlapping_and_touching_ranges() {
+        let mut ranges = set(&[(10, 20), (30, 40), (50, 60)]);
+        assert_eq!(ranges.ranges(), &[(10, 20), (30, 40), (50, 60)]);
+        ranges.insert(20, 30);
+        assert_eq!(ranges.ranges(), &[(10, 40), (50, 60)]);
+        ranges.insert(0, 5);
+        ranges.insert(35, 55);
+        assert_eq!(ranges.ranges(), &[(0, 5), (10, 60)]);
+        ranges.insert(7, 7);
+        assert_eq!(ranges.ranges(), &[(0, 5), (10, 60)]);
+        assert_eq!(ranges.total(), 55);
+    }
+
+    #[test]
+    fn remove_splits_and_trims_ranges() {
+        let mut ranges = set(&[(0, 100)]);
+        ranges.remove(40, 60);
+        assert_eq!(ranges.ranges(), &[(0, 40), (60, 100)]);
+        ranges.remove(30, 70);
+        assert_eq!(ranges.ranges(), &[(0, 30), (70, 100)]);
+        ranges.remove(100, 200);
+        ranges.remove(0, 30);
+        assert_eq!(ranges.ranges(), &[(70, 100)]);
+        ranges.remove(0, 1000);
+        assert_eq!(ranges.ranges(), &[] as &[(u64, u64)]);
+    }
+
+    #[test]
+    fn complement_is_what_the_set_misses() {
+        let ranges = set(&[(10, 20), (30, 40)]);
+        assert_eq!(ranges.complement(0, 50).ranges(), &[(0, 10), (20, 30), (40, 50)]);
+        assert_eq!(ranges.complement(15, 35).ranges(), &[(20, 30)]);
+    }
+
+    #[test]
+    fn gaps_are_nounces_in_no_set() {
+        let mut coverage = Coverage::default();
+        coverage.lease(0, 100);
+        coverage.lease(100, 200);
+        coverage.lease(300, 400);
+        coverage.complete(0, 50);
+        coverage.abandon(50, 100);
+        assert_eq!(coverage.completed.ranges(), &[(0, 50)]);
+        assert_eq!(coverage.abandoned.ranges(), &[(50, 100)]);
+        assert_eq!(coverage.gaps(400).ranges(), &[(200, 300)]);
+
+        // Abandoning a range never marks what was searched as abandoned.
+        coverage.abandon(0, 200);
+        assert_eq!(coverage.abandoned.ranges(), &[(50, 200)]);
+        assert_eq!(coverage.gaps(500).ranges(), &[(200, 300), (400, 500)]);
+    }
+}
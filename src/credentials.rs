@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+
+use actix_web::HttpRequest;
+use hmac::{Hmac, Mac, NewMac};
+use serde::Deserialize;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::error::{ApiError, ErrorCode};
+
+/// Header clients send their API key in.
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// Header the admin token is sent in.
+pub const ADMIN_TOKEN_HEADER: &str = "X-Admin-Token";
+
+/// Header clients send the HMAC of a signed request body in.
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+
+/// Issued API keys and signing secrets.
+///
+/// Only a SHA-256 of each key is kept, so the registry on disk can not be used to sign in.
+/// Secrets have to be kept as they are to check signatures with.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Credentials {
+    /// Hex SHA-256 of each student's key, by student number.
+    keys: HashMap<String, String>,
+    /// Each student's signing secret, by student number. Missing for keys issued before
+    /// signing.
+    #[serde(default)]
+    secrets: HashMap<String, String>,
+}
+
+impl Credentials {
+    /// Makes a new key and signing secret for `student_number`, replacing any it had. Returns
+    /// `(key, secret)`. The key can not be recovered later.
+    pub fn issue(&mut self, student_number: &str) -> (String, String) {
+        let key = crate::util::random_hex(32);
+        let secret = crate::util::random_hex(32);
+        self.keys.insert(String::from(student_number), hash_key(&key));
+        self.secrets.insert(String::from(student_number), secret.clone());
+        (key, secret)
+    }
+
+    /// Checks the signature header of `req` is the HMAC-SHA256 of `body`, keyed with the
+    /// student's secret as it was issued (the hex string, not the bytes it encodes).
+    pub fn verify_signature(&self, req: &HttpRequest, student_number: &str, body: &[u8]) -> Result<(), ApiError> {
+        let secret = self.secrets.get(student_number).ok_or(ErrorCode::NoSigningSecret)?;
+        let signature = req.headers().get(SIGNATURE_HEADER)
+            .ok_or(ErrorCode::MissingSignature)?
+            .to_str()
+            .ok()
+            .and_then(|signature| crate::util::decode_hex(signature).ok())
+            .ok_or(ErrorCode::BadSignature)?;
+        let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("HMAC takes keys of any length");
+        mac.update(body);
+        mac.verify(&signature).map_err(|_| ErrorCode::BadSignature.into())
+    }
+
+    /// Checks that `key` was issued to `student_number`.
+    pub fn check(&self, student_number: &str, key: &str) -> Result<(), ApiError> {
+        let hash = hash_key(key);
+        if self.keys.get(student_number) == Some(&hash) {
+            Ok(())
+        } else if self.keys.values().any(|issued| *issued == hash) {
+            Err(ErrorCode::WrongStudent.into())
+        } else {
+            Err(ErrorCode::UnknownApiKey.into())
+        }
+    }
+
+    /// Checks the API key header of `req` against `student_number`.
+    pub fn authenticate(&self, req: &HttpRequest, student_number: &str) -> Result<(), ApiError> {
+        let key = req.headers().get(API_KEY_HEADER)
+            .ok_or(ErrorCode::MissingApiKey)?
+            .to_str()
+            .map_err(|_| ErrorCode::UnknownApiKey)?;
+        self.check(student_number, key)
+    }
+}
+
+/// Checks the admin token header of `req` against `admin_token`. An empty token turns the
+/// admin API off.
+pub fn authenticate_admin(req: &HttpRequest, admin_token: &str) -> Result<(), ApiError> {
+    if admin_token.is_empty() {
+        return Err(ErrorCode::AdminDisabled.into());
+    }
+    let token = req.headers().get(ADMIN_TOKEN_HEADER)
+        .ok_or(ErrorCode::MissingAdminToken)?
+        .to_str()
+        .map_err(|_| ErrorCode::WrongAdminToken)?;
+    // Compared as hashes so the time taken does not give the token away.
+    if hash_key(token) == hash_key(admin_token) {
+        Ok(())
+    } else {
+        Err(ErrorCode::WrongAdminToken.into())
+    }
+}
+
+fn hash_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn code(result: Result<(), ApiError>) -> Option<ErrorCode> {
+        result.err().map(|e| e.code)
+    }
+
+    #[test]
+    fn keys_only_sign_in_their_own_student() {
+        let mut credentials = Credentials::default();
+        let (key, _) = credentials.issue("a");
+        let (other, _) = credentials.issue("b");
+        assert!(credentials.check("a", &key).is_ok());
+        assert_eq!(code(credentials.check("a", &other)), Some(ErrorCode::WrongStudent));
+        assert_eq!(code(credentials.check("a", "made up")), Some(ErrorCode::UnknownApiKey));
+
+        // Issuing again replaces the old key.
+        let (new_key, _) = credentials.issue("a");
+        assert!(credentials.check("a", &new_key).is_ok());
+        assert_eq!(code(credentials.check("a", &key)), Some(ErrorCode::UnknownApiKey));
+    }
+
+    #[test]
+    fn keys_are_read_from_the_header() {
+        let mut credentials = Credentials::default();
+        let (key, _) = credentials.issue("a");
+        let with_key = TestRequest::default().header(API_KEY_HEADER, key.as_str()).to_http_request();
+        assert!(credentials.authenticate(&with_key, "a").is_ok());
+        let without = TestRequest::default().to_http_request();
+        assert_eq!(code(credentials.authenticate(&without, "a")), Some(ErrorCode::MissingApiKey));
+    }
+
+    fn sign(secret: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).unwrap();
+        mac.update(body);
+        mac.finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn signature_is_the_hmac_of_the_body() {
+        let mut credentials = Credentials::default();
+        let (_, secret) = credentials.issue("a");
+        let body = b"{\"job_n\":1}";
+        let signed = |signature: &str| TestRequest::default().header(SIGNATURE_HEADER, signature).to_http_request();
+
+        assert!(credentials.verify_signature(&signed(&sign(&secret, body)), "a", body).is_ok());
+        let other_body = credentials.verify_signature(&signed(&sign(&secret, body)), "a", b"{\"job_n\":2}");
+        assert_eq!(code(other_body), Some(ErrorCode::BadSignature));
+        let other_secret = credentials.verify_signature(&signed(&sign("other", body)), "a", body);
+        assert_eq!(code(other_secret), Some(ErrorCode::BadSignature));
+        let not_hex = credentials.verify_signature(&signed("not hex"), "a", body);
+        assert_eq!(code(not_hex), Some(ErrorCode::BadSignature));
+
+        let unsigned = TestRequest::default().to_http_request();
+        assert_eq!(code(credentials.verify_signature(&unsigned, "a", body)), Some(ErrorCode::MissingSignature));
+        let no_secret = credentials.verify_signature(&signed(&sign(&secret, body)), "b", body);
+        assert_eq!(code(no_secret), Some(ErrorCode::NoSigningSecret));
+    }
+
+    #[test]
+    fn admin_token_is_checked_and_empty_disables() {
+        let with = |token: &str| TestRequest::default().header(ADMIN_TOKEN_HEADER, token).to_http_request();
+        assert!(authenticate_admin(&with("secret"), "secret").is_ok());
+        assert_eq!(code(authenticate_admin(&with("guess"), "secret")), Some(ErrorCode::WrongAdminToken));
+        assert_eq!(code(authenticate_admin(&TestRequest::default().to_http_request(), "secret")), Some(ErrorCode::MissingAdminToken));
+        assert_eq!(code(authenticate_admin(&with(""), "")), Some(ErrorCode::AdminDisabled));
+    }
+}
@@ -0,0 +1,238 @@
+use std::fmt;
+
+use actix_web::dev::HttpResponseBuilder;
+use actix_web::error::BlockingError;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::credentials::{ADMIN_TOKEN_HEADER, API_KEY_HEADER, SIGNATURE_HEADER};
+
+/// Machine readable reason a request failed. The serialized names are part of the protocol
+/// and must not change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// The body or path could not be parsed.
+    InvalidRequest,
+    /// No API key header was sent.
+    MissingApiKey,
+    /// The key was never issued, or has been replaced.
+    UnknownApiKey,
+    /// The key belongs to a different student number.
+    WrongStudent,
+    /// The student was issued a key before signing secrets existed.
+    NoSigningSecret,
+    /// No signature header was sent.
+    MissingSignature,
+    /// The signature is not the HMAC of the body.
+    BadSignature,
+    /// `timestamp` is further from the server's clock than `signature_max_age`.
+    StaleTimestamp,
+    /// `sequence` is not higher than the machine's last one, so the packet is a replay.
+    ReplayedSequence,
+    /// The student number is banned.
+    Banned,
+    /// The machine sent mostly invalid solutions and may not take or submit jobs.
+    Quarantined,
+    /// No admin token is configured.
+    AdminDisabled,
+    MissingAdminToken,
+    WrongAdminToken,
+    NotFound,
+    /// The job number is not pending for this student.
+    NoPendingJob,
+    /// `nounce_start` does not match the leased job.
+    InvalidNounceStart,
+    /// No competition round is accepting submissions.
+    NoActiveRound,
+    RateLimited,
+    /// The machine holds `max_pending_jobs` jobs already.
+    TooManyPendingJobs,
+    /// The student has `max_machines` machines already.
+    TooManyMachines,
+    Internal,
+}
+
+crate::util::wire_names!(ErrorCode {
+    InvalidRequest => "invalid_request",
+    MissingApiKey => "missing_api_key",
+    UnknownApiKey => "unknown_api_key",
+    WrongStudent => "wrong_student",
+    NoSigningSecret => "no_signing_secret",
+    MissingSignature => "missing_signature",
+    BadSignature => "bad_signature",
+    StaleTimestamp => "stale_timestamp",
+    ReplayedSequence => "replayed_sequence",
+    Banned => "banned",
+    Quarantined => "quarantined",
+    AdminDisabled => "admin_disabled",
+    MissingAdminToken => "missing_admin_token",
+    WrongAdminToken => "wrong_admin_token",
+    NotFound => "not_found",
+    NoPendingJob => "no_pending_job",
+    InvalidNounceStart => "invalid_nounce_start",
+    NoActiveRound => "no_active_round",
+    RateLimited => "rate_limited",
+    TooManyPendingJobs => "too_many_pending_jobs",
+    TooManyMachines => "too_many_machines",
+    Internal => "internal",
+});
+
+impl ErrorCode {
+    pub fn status(self) -> StatusCode {
+        match self {
+            ErrorCode::InvalidRequest => StatusCode::BAD_REQUEST,
+            ErrorCode::MissingApiKey
+            | ErrorCode::UnknownApiKey
+            | ErrorCode::NoSigningSecret
+            | ErrorCode::MissingSignature
+            | ErrorCode::BadSignature
+            | ErrorCode::StaleTimestamp
+            | ErrorCode::MissingAdminToken
+            | ErrorCode::WrongAdminToken => StatusCode::UNAUTHORIZED,
+            ErrorCode::WrongStudent
+            | ErrorCode::Banned
+            | ErrorCode::Quarantined
+            | ErrorCode::TooManyMachines
+            | ErrorCode::AdminDisabled => StatusCode::FORBIDDEN,
+            ErrorCode::NotFound | ErrorCode::NoPendingJob => StatusCode::NOT_FOUND,
+            ErrorCode::ReplayedSequence | ErrorCode::InvalidNounceStart | ErrorCode::NoActiveRound => {
+                StatusCode::CONFLICT
+            }
+            ErrorCode::RateLimited | ErrorCode::TooManyPendingJobs => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Message used when the error site has nothing more specific to say.
+    pub fn message(self) -> String {
+        match self {
+            ErrorCode::InvalidRequest => String::from("invalid request"),
+            ErrorCode::MissingApiKey => format!("missing {} header", API_KEY_HEADER),
+            ErrorCode::UnknownApiKey => String::from("unknown API key"),
+            ErrorCode::WrongStudent => String::from("API key was issued to another student number"),
+            ErrorCode::NoSigningSecret => String::from("no signing secret was issued, ask for a new key"),
+            ErrorCode::MissingSignature => format!("missing {} header", SIGNATURE_HEADER),
+            ErrorCode::BadSignature => String::from("signature does not match the request body"),
+            ErrorCode::StaleTimestamp => String::from("timestamp is too far from the server's clock"),
+            ErrorCode::ReplayedSequence => String::from("sequence number was already used"),
+            ErrorCode::Banned => String::from("student number is banned"),
+            ErrorCode::Quarantined => String::from("machine is quarantined"),
+            ErrorCode::AdminDisabled => String::from("the admin API is disabled"),
+            ErrorCode::MissingAdminToken => format!("missing {} header", ADMIN_TOKEN_HEADER),
+            ErrorCode::WrongAdminToken => String::from("wrong admin token"),
+            ErrorCode::NotFound => String::from("not found"),
+            ErrorCode::NoPendingJob => String::from("job is not pending"),
+            ErrorCode::InvalidNounceStart => String::from("nounce_start does not match the job"),
+            ErrorCode::NoActiveRound => String::from("no competition round is active"),
+            ErrorCode::RateLimited => String::from("rate limited"),
+            ErrorCode::TooManyPendingJobs => String::from("too many pending jobs, submit one first"),
+            ErrorCode::TooManyMachines => String::from("too many machines, reuse a machine name"),
+            ErrorCode::Internal => String::from("internal server error"),
+        }
+    }
+}
+
+/// Body of every failed request.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+    /// Seconds to wait before trying again, for errors that pass with time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<f64>,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        ApiError {
+            code,
+            message: message.into(),
+            retry_after: None,
+        }
+    }
+
+    pub fn with_retry_after(mut self, retry_after: f64) -> Self {
+        self.message = format!("{}, retry after {:.1} seconds", self.message, retry_after);
+        self.retry_after = Some(retry_after);
+        self
+    }
+
+    /// Response with the status of the code and a `Retry-After` header, in whole seconds, if
+    /// there is a retry time. For routes that wrap the error in their own packet.
+    pub fn response_builder(&self) -> HttpResponseBuilder {
+        let mut response = HttpResponse::build(self.code.status());
+        if let Some(retry_after) = self.retry_after {
+            response.header("Retry-After", retry_after.ceil().max(1.0).to_string());
+        }
+        response
+    }
+}
+
+impl From<ErrorCode> for ApiError {
+    fn from(code: ErrorCode) -> Self {
+        ApiError::new(code, code.message())
+    }
+}
+
+/// Storage failures are logged and reported without detail.
+impl From<std::io::Error> for ApiError {
+    fn from(e: std::io::Error) -> Self {
+        eprintln!("request failed: {}", e);
+        ErrorCode::Internal.into()
+    }
+}
+
+impl<E: Into<ApiError> + fmt::Debug> From<BlockingError<E>> for ApiError {
+    fn from(e: BlockingError<E>) -> Self {
+        match e {
+            BlockingError::Error(e) => e.into(),
+            BlockingError::Canceled => {
+                eprintln!("request failed: blocking task was canceled");
+                ErrorCode::Internal.into()
+            }
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.code.status()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        self.response_builder().json(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_carry_their_code_and_status() {
+        let error: ApiError = ErrorCode::TooManyPendingJobs.into();
+        let body = serde_json::to_value(error.clone().with_retry_after(2.5)).unwrap();
+        assert_eq!(body["code"], "too_many_pending_jobs");
+        assert_eq!(body["retry_after"], 2.5);
+        assert!(serde_json::to_value(&error).unwrap().get("retry_after").is_none());
+
+        let response = error.with_retry_after(2.5).error_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get("Retry-After").unwrap(), "3");
+
+        assert_eq!(ErrorCode::WrongStudent.status(), StatusCode::FORBIDDEN);
+        assert_eq!(ErrorCode::MissingApiKey.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(ErrorCode::NoPendingJob.status(), StatusCode::NOT_FOUND);
+        let io: ApiError = std::io::Error::other("disk full").into();
+        assert_eq!(io.code, ErrorCode::Internal);
+        assert_eq!(io.message, "internal server error");
+    }
+}
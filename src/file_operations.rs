@@ -1,7 +1,8 @@
 use std::fs::OpenOptions;
+use std::io::Write;
 
 fn make_path(path: &str) {
-    let dir_path = format!("{}/{}/{}", get_cwd(), "data", path);
+    let dir_path = format!("{}/{}", data_dir(), path);
     let err_msg = format!("could not create directory: {}", dir_path);
     std::fs::create_dir_all(&dir_path).expect(&err_msg);
 }
@@ -12,13 +13,22 @@ pub fn get_cwd() -> String {
     format!("{}", cwd)
 }
 
+/// Path of the configured data directory, made absolute against the cwd.
+pub fn data_dir() -> String {
+    let dir = &crate::config::get().data_dir;
+    if std::path::Path::new(dir).is_absolute() {
+        dir.clone()
+    } else {
+        format!("{}/{}", get_cwd(), dir)
+    }
+}
+
 pub fn open_append_file(path: &str, filename: &str) -> Result<std::fs::File, std::io::Error> {
     make_path(path);
     OpenOptions::new()
         .create(true)
-        .write(true)
         .append(true)
-        .open(&format!("{}/data/{}/{}", get_cwd(), path, filename))
+        .open(format!("{}/{}/{}", data_dir(), path, filename))
 }
 
 pub fn open_overwrite_file(path: &str, filename: &str) -> Result<std::fs::File, std::io::Error> {
@@ -27,12 +37,42 @@ pub fn open_overwrite_file(path: &str, filename: &str) -> Result<std::fs::File,
         .create(true)
         .write(true)
         .truncate(true)
-        .open(&format!("{}/data/{}/{}", get_cwd(), path, filename))
+        .open(format!("{}/{}/{}", data_dir(), path, filename))
 }
 
 pub fn open_read_file(path: &str, filename: &str) -> Result<std::fs::File, std::io::Error> {
     make_path(path);
     OpenOptions::new()
         .read(true)
-        .open(&format!("{}/data/{}/{}", get_cwd(), path, filename))
+        .open(format!("{}/{}/{}", data_dir(), path, filename))
+}
+
+/// Replaces `filename` with `contents` so that a crash leaves either the old or the new file.
+///
+/// The contents are written to `<filename>.tmp` and synced, the current file is copied to
+/// `<filename>.bak`, then the temporary file is renamed over the current one.
+pub fn write_atomic(path: &str, filename: &str, contents: &[u8]) -> Result<(), std::io::Error> {
+    make_path(path);
+    write_atomic_in(&format!("{}/{}", data_dir(), path), filename, contents)
+}
+
+/// `write_atomic` for a file in `dir`, a directory that already exists.
+pub fn write_atomic_in(dir: &str, filename: &str, contents: &[u8]) -> Result<(), std::io::Error> {
+    let current = format!("{}/{}", dir, filename);
+    let tmp = format!("{}.tmp", current);
+    {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&tmp)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+    }
+    if std::path::Path::new(&current).exists() {
+        std::fs::copy(&current, format!("{}.bak", current))?;
+    }
+    std::fs::rename(&tmp, &current)?;
+    // Make the rename itself durable.
+    std::fs::File::open(dir)?.sync_all()
 }
\ No newline at end of file
@@ -0,0 +1,202 @@
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::fmt::Write;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::storage::Storage;
+
+/// Lines the hash log may hold beyond one per known hash before it is compacted.
+const MAX_EXTRA_LINES: usize = 10_000;
+/// Seconds to wait after a failed compaction before trying again.
+const COMPACTION_RETRY_INTERVAL: f64 = 60.0;
+
+/// A 32 byte hash, as accepted solutions carry.
+pub type Hash = [u8; 32];
+
+/// Parses a hash from its 64 hex digits, either case.
+pub fn parse_hash(hash: &str) -> Option<Hash> {
+    crate::util::decode_hex(hash).ok()?.try_into().ok()
+}
+
+/// The hash as it is written to the log, in lowercase hex.
+fn hash_to_string(hash: &Hash) -> String {
+    let mut text = String::with_capacity(64);
+    for byte in hash {
+        let _ = write!(text, "{:02x}", byte);
+    }
+    text
+}
+
+/// Set of every hash the pool has accepted.
+///
+/// The set is held in memory so duplicate checks do not touch the storage. Each new hash is
+/// appended to the storage's hash log, which is reloaded at startup. The log is compacted when
+/// it holds too many duplicate or blank lines (left behind by the old linear-scan store or a
+/// crash), or when an append failed and it is missing hashes.
+///
+/// Compaction writes a copy of the set without holding the lock, so duplicate checks go on
+/// meanwhile. Hashes inserted during the write are appended again to the new log.
+///
+/// Appends are not synced one by one, call `Storage::sync_hashes` before anything that relies
+/// on them is saved.
+#[derive(Debug)]
+pub struct HashStore {
+    state: Mutex<HashState>,
+    /// Held while compacting, so only one compaction runs at a time.
+    compacting: Mutex<()>,
+    storage: Arc<dyn Storage>,
+}
+
+#[derive(Debug)]
+struct HashState {
+    hashes: HashSet<Hash>,
+    /// Lines in the hash log, blank and duplicate ones included.
+    lines_in_log: usize,
+    /// Set when a hash could not be appended, until the log is compacted.
+    missing_hashes: bool,
+    /// Hashes inserted since the running compaction copied the set, if one is running.
+    inserted_while_compacting: Option<Vec<Hash>>,
+    /// Unix time before which no compaction is tried, set when one fails.
+    retry_compaction_after: f64,
+}
+
+impl HashState {
+    fn needs_compaction(&self, now: f64) -> bool {
+        (self.missing_hashes || self.lines_in_log > self.hashes.len() + MAX_EXTRA_LINES)
+            && now >= self.retry_compaction_after
+    }
+}
+
+impl HashStore {
+    /// Loads the hash log into memory. Lines that are not hashes are dropped by compaction.
+    pub fn open(storage: Arc<dyn Storage>) -> Self {
+        let lines = storage.load_hashes();
+        let lines_in_log = lines.len();
+        let hashes: HashSet<Hash> = lines.iter()
+            .filter_map(|line| parse_hash(line))
+            .collect();
+        let needs_compaction = lines_in_log > hashes.len();
+        let store = HashStore {
+            state: Mutex::new(HashState {
+                hashes,
+                lines_in_log,
+                missing_hashes: false,
+                inserted_while_compacting: None,
+                retry_compaction_after: 0.0,
+            }),
+            compacting: Mutex::new(()),
+            storage,
+        };
+        if needs_compaction {
+            store.compact();
+        }
+        store
+    }
+
+    /// Records the hash. Returns false if it had already been recorded.
+    pub fn insert(&self, hash: &Hash) -> bool {
+        let mut state = self.state.lock();
+        if !state.hashes.insert(*hash) {
+            return false;
+        }
+        if let Some(inserted) = state.inserted_while_compacting.as_mut() {
+            inserted.push(*hash);
+        }
+        match self.storage.append_hash(&hash_to_string(hash)) {
+            Ok(()) => state.lines_in_log += 1,
+            Err(e) => {
+                eprintln!("Could not append to the hash log: {}", e);
+                state.missing_hashes = true;
+            }
+        }
+        let needs_compaction = state.needs_compaction(crate::util::get_time());
+        drop(state);
+        if needs_compaction {
+            self.compact();
+        }
+        true
+    }
+
+    /// Rewrites the hash log so it holds exactly one line per known hash. Does nothing if a
+    /// compaction is already running.
+    pub fn compact(&self) {
+        let _compacting = match self.compacting.try_lock() {
+            Some(guard) => guard,
+            None => return,
+        };
+        let snapshot: Vec<Hash> = {
+            let mut state = self.state.lock();
+            state.inserted_while_compacting = Some(vec![]);
+            state.hashes.iter().copied().collect()
+        };
+        let result = self.storage.rewrite_hashes(&mut snapshot.iter().map(hash_to_string));
+
+        let mut state = self.state.lock();
+        let inserted = state.inserted_while_compacting.take().unwrap_or_default();
+        match result {
+            Ok(()) => {
+                state.lines_in_log = snapshot.len();
+                state.missing_hashes = false;
+                // They may have gone to the replaced log.
+                for hash in inserted.iter() {
+                    match self.storage.append_hash(&hash_to_string(hash)) {
+                        Ok(()) => state.lines_in_log += 1,
+                        Err(_) => state.missing_hashes = true,
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("Could not compact the hash log: {}", e);
+                state.retry_compaction_after = crate::util::get_time() + COMPACTION_RETRY_INTERVAL;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn hash(byte: u8) -> Hash {
+        [byte; 32]
+    }
+
+    #[test]
+    fn hashes_survive_a_reload() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let store = HashStore::open(Arc::clone(&storage));
+        assert!(store.insert(&hash(0xaa)));
+        assert!(store.insert(&hash(0xbb)));
+        assert!(!store.insert(&hash(0xaa)));
+        assert_eq!(storage.load_hashes(), vec!["aa".repeat(32), "bb".repeat(32)]);
+
+        let reloaded = HashStore::open(Arc::clone(&storage));
+        assert!(!reloaded.insert(&hash(0xbb)));
+        assert!(reloaded.insert(&hash(0xcc)));
+    }
+
+    #[test]
+    fn duplicate_and_blank_lines_are_compacted_at_startup() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let (aa, bb) = ("aa".repeat(32), "BB".repeat(32));
+        for line in [&aa, "", &bb, &aa, "not a hash"].iter() {
+            storage.append_hash(line).unwrap();
+        }
+        let store = HashStore::open(Arc::clone(&storage));
+        assert_eq!(store.state.lock().lines_in_log, 2);
+        let mut lines = storage.load_hashes();
+        lines.sort();
+        assert_eq!(lines, vec![aa, "bb".repeat(32)]);
+    }
+
+    #[test]
+    fn parse_hash_takes_only_32_bytes_of_hex() {
+        assert_eq!(parse_hash(&"aB".repeat(32)), Some([0xab; 32]));
+        assert_eq!(parse_hash(&"ab".repeat(31)), None);
+        assert_eq!(parse_hash(&"ab".repeat(33)), None);
+        assert_eq!(parse_hash(&"zz".repeat(32)), None);
+    }
+}
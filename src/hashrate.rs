@@ -0,0 +1,202 @@
+use std::collections::VecDeque;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::submitter::{Liveness, Submitter};
+
+/// Windows the effective hashrate is shown over, in seconds: 5 minutes, an hour and a day.
+pub const WINDOWS: [f64; 3] = [5.0 * 60.0, 60.0 * 60.0, 24.0 * 60.0 * 60.0];
+
+const MINUTE: f64 = 60.0;
+const HOUR: f64 = 60.0 * 60.0;
+
+/// Work of accepted shares in one minute buckets for the last hour and one hour buckets for
+/// the last day. Enough to estimate the hashrate a machine really has, whatever it reports.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct WorkHistory {
+    /// Unix time of the first submission recorded, zero if there has been none.
+    started: f64,
+    /// `(minute, work)` pairs, oldest first.
+    minutes: VecDeque<(u64, f64)>,
+    /// `(hour, work)` pairs, oldest first.
+    hours: VecDeque<(u64, f64)>,
+}
+
+impl WorkHistory {
+    /// Records `work` done by a submission at `now`. Submissions without shares are recorded
+    /// too, so a machine's first share is not taken as all it did.
+    pub fn record(&mut self, now: f64, work: f64) {
+        if self.started == 0.0 {
+            self.started = now;
+        }
+        add(&mut self.minutes, (now / MINUTE) as u64, work, 60);
+        add(&mut self.hours, (now / HOUR) as u64, work, 24);
+    }
+
+    /// Hashes a second over the last `window` seconds, or since the first submission if that
+    /// is more recent. Windows longer than an hour are measured in whole hours.
+    pub fn hashrate(&self, now: f64, window: f64) -> f64 {
+        if self.started == 0.0 {
+            return 0.0;
+        }
+        let (buckets, length) = if window <= HOUR {
+            (&self.minutes, MINUTE)
+        } else {
+            (&self.hours, HOUR)
+        };
+        let window_start = now - window;
+        let work: f64 = buckets.iter()
+            .map(|&(index, work)| {
+                // Only the part of a bucket inside the window counts.
+                let start = index as f64 * length;
+                let inside = ((start + length - window_start) / length).clamp(0.0, 1.0);
+                work * inside
+            })
+            .sum();
+        // At least a minute, so one lucky share does not look like a huge rate.
+        let elapsed = window.min(now - self.started).max(MINUTE);
+        work / elapsed
+    }
+
+    /// Hashrate over each of `WINDOWS`.
+    pub fn effective(&self, now: f64) -> EffectiveHashrate {
+        let [last_5m, last_1h, last_24h] = WINDOWS.map(|window| self.hashrate(now, window));
+        EffectiveHashrate { last_5m, last_1h, last_24h }
+    }
+}
+
+/// Hashes a second worked out from accepted shares, over each of `WINDOWS`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct EffectiveHashrate {
+    pub last_5m: f64,
+    pub last_1h: f64,
+    pub last_24h: f64,
+}
+
+impl std::iter::Sum for EffectiveHashrate {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(EffectiveHashrate::default(), |total, rate| EffectiveHashrate {
+            last_5m: total.last_5m + rate.last_5m,
+            last_1h: total.last_1h + rate.last_1h,
+            last_24h: total.last_24h + rate.last_24h,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MachineHashrate {
+    pub name: String,
+    pub liveness: Liveness,
+    /// Average of what the machine reports, whether or not it is still online.
+    pub reported: f64,
+    pub effective: EffectiveHashrate,
+}
+
+/// A submitter's hashrate, in total and machine by machine.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SubmitterHashrate {
+    /// Sum of what the machines that are not offline report.
+    pub reported: f64,
+    /// Sum over every machine, offline ones may have worked during the windows.
+    pub effective: EffectiveHashrate,
+    pub machines: Vec<MachineHashrate>,
+}
+
+impl SubmitterHashrate {
+    pub fn of(submitter: &Submitter, now: f64) -> Self {
+        let machines: Vec<MachineHashrate> = submitter.machines.iter()
+            .map(|machine| MachineHashrate {
+                name: machine.name.clone(),
+                liveness: machine.liveness(now),
+                reported: machine.reported_total_hashrate,
+                effective: machine.work_history.effective(now),
+            })
+            .collect();
+        SubmitterHashrate {
+            reported: machines.iter()
+                .filter(|machine| machine.liveness != Liveness::Offline)
+                .fold(0.0, |total, machine| total + machine.reported),
+            effective: machines.iter().map(|machine| machine.effective).sum(),
+            machines,
+        }
+    }
+}
+
+/// Adds `work` to the bucket `index`, dropping buckets more than `keep` behind it.
+fn add(buckets: &mut VecDeque<(u64, f64)>, index: u64, work: f64, keep: u64) {
+    match buckets.back_mut() {
+        Some((last, total)) if *last == index => *total += work,
+        _ => buckets.push_back((index, work)),
+    }
+    while buckets.front().is_some_and(|&(first, _)| first + keep <= index) {
+        buckets.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An hour boundary, so minute and hour buckets line up.
+    const T0: f64 = 1000.0 * HOUR;
+
+    #[test]
+    fn hashrate_counts_work_inside_each_window() {
+        let mut history = WorkHistory::default();
+        assert_eq!(history.hashrate(T0, WINDOWS[0]), 0.0);
+        history.record(T0, 600.0);
+        history.record(T0 + 9.0 * MINUTE, 600.0);
+
+        let now = T0 + 10.0 * MINUTE;
+        // Only the second share is in the last five minutes.
+        assert_eq!(history.hashrate(now, WINDOWS[0]), 2.0);
+        // Longer windows are cut short at the first submission.
+        assert_eq!(history.hashrate(now, WINDOWS[1]), 2.0);
+        assert_eq!(history.hashrate(now, WINDOWS[2]), 2.0);
+    }
+
+    #[test]
+    fn hashrate_is_measured_over_at_least_a_minute() {
+        let mut history = WorkHistory::default();
+        history.record(T0, 60.0);
+        assert_eq!(history.hashrate(T0 + 1.0, WINDOWS[0]), 1.0);
+    }
+
+    #[test]
+    fn old_buckets_are_dropped() {
+        let mut history = WorkHistory::default();
+        history.record(T0, 3600.0);
+        history.record(T0 + 2.0 * HOUR, 0.0);
+        assert_eq!(history.minutes.len(), 1);
+        assert_eq!(history.hashrate(T0 + 2.0 * HOUR, WINDOWS[1]), 0.0);
+        assert_eq!(history.hashrate(T0 + 2.0 * HOUR, WINDOWS[2]), 3600.0 / (2.0 * HOUR));
+
+        history.record(T0 + 25.0 * HOUR, 0.0);
+        assert_eq!(history.hours.len(), 2);
+        assert_eq!(history.hashrate(T0 + 25.0 * HOUR, WINDOWS[2]), 0.0);
+    }
+
+    #[test]
+    fn submitters_sum_reports_of_active_machines_and_all_work() {
+        let config = crate::config::get();
+        let now = T0 + 10.0 * MINUTE;
+        let mut submitter = Submitter::new("s");
+        for (name, last_seen, reported) in [("here", now, 100.0), ("gone", now - config.machine_offline_after, 50.0)].iter() {
+            let machine = submitter.get_machine(name);
+            machine.touch(*last_seen);
+            machine.reported_total_hashrate = *reported;
+            machine.work_history.record(T0, 0.0);
+            machine.work_history.record(now - 1.0, 600.0);
+        }
+
+        let hashrate = SubmitterHashrate::of(&submitter, now);
+        assert_eq!(hashrate.reported, 100.0);
+        assert_eq!(hashrate.effective.last_5m, 4.0);
+        assert_eq!(hashrate.effective.last_1h, 2.0);
+        assert_eq!(hashrate.machines.len(), 2);
+        assert_eq!(hashrate.machines[1].liveness, Liveness::Offline);
+        assert_eq!(hashrate.machines[1].reported, 50.0);
+        assert_eq!(hashrate.machines[1].effective.last_5m, 2.0);
+    }
+}
@@ -1,36 +1,150 @@
+mod admin;
 mod app;
+mod bans;
+mod config;
+mod competition;
 mod constants;
+mod coverage;
+mod credentials;
+mod error;
 mod packets;
+mod pow;
+mod rate_limit;
+mod rewards;
 mod routes;
 mod util;
 mod file_operations;
+mod hashrate;
+mod hash_store;
+mod metrics;
+mod storage;
 mod submitter;
+mod timeseries;
 
-use crate::routes::{boot, index, job_request, job_submit, pool_status, showdown};
+use crate::config::StorageBackend;
+use crate::error::{ApiError, ErrorCode};
+use crate::storage::{FileStorage, MemoryStorage, Storage};
+use crate::routes::{boot, competition_round, competition_rounds, heartbeat, index, job_request, job_submit, pool_history,
+    pool_status, prometheus_metrics, reward_balances, showdown, submitter_coverage, submitter_history, submitter_rewards};
 use actix_web::middleware::Logger;
-use actix_web::{App, HttpServer};
-use std::sync::{Arc, Mutex};
+use actix_web::{web, App, HttpServer};
+use std::sync::Arc;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    // std::env::set_var("RUST_LOG", "actix_web=info");
-    std::env::set_var("RUST_LOG", "actix_web=debug");
+    let config = match config::Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("hasher_agg: {}", e);
+            std::process::exit(2);
+        }
+    };
     std::env::set_var("RUST_BACKTRACE", "1");
-    env_logger::init();
-    let data = Arc::new(Mutex::new(app::ApplicationData::begin()));
-    let app = Arc::clone(&data);
+    env_logger::Builder::new().parse_filters(&config.log_level).init();
+    let bind_address = config.bind_address.clone();
+    let storage_backend = config.storage;
+    let issue_key = config.issue_key.clone();
+    config::init(config);
+    let storage: Arc<dyn Storage> = match storage_backend {
+        StorageBackend::Filesystem => {
+            let storage = FileStorage::new();
+            for repair in storage.recover() {
+                eprintln!("recovery: {}", repair);
+            }
+            Arc::new(storage)
+        }
+        StorageBackend::Memory => Arc::new(MemoryStorage::new()),
+    };
+    if let Some(student_number) = issue_key {
+        if storage_backend == StorageBackend::Memory {
+            eprintln!("hasher_agg: keys issued with memory storage would be lost on exit");
+            std::process::exit(2);
+        }
+        let mut credentials = storage.load_credentials().unwrap_or_default();
+        let (key, secret) = credentials.issue(&student_number);
+        storage.save_credentials(&credentials)?;
+        println!("key: {}", key);
+        println!("secret: {}", secret);
+        return Ok(());
+    }
+    let data = web::Data::new(app::ApplicationData::begin(storage));
+    // Checked every `machine_stale_after` so no job is held much past `machine_offline_after`
+    // by a machine that stopped.
+    let config = config::get();
+    spawn_periodic(
+        data.clone(),
+        config.machine_stale_after,
+        "reclaiming offline machines' jobs",
+        app::ApplicationData::reclaim_offline_jobs,
+    );
+    spawn_periodic(
+        data.clone(),
+        config.save_interval,
+        "saving the reward ledger and competition rounds",
+        app::ApplicationData::flush,
+    );
+    if config.timeseries_interval > 0.0 {
+        spawn_periodic(
+            data.clone(),
+            config.timeseries_interval,
+            "recording a time series sample",
+            app::ApplicationData::record_sample,
+        );
+    }
+    let app = data.clone();
     let server = HttpServer::new(move || {
         App::new()
-            .data(Arc::clone(&app))
+            .app_data(data.clone())
+            .app_data(web::JsonConfig::default().error_handler(|e, _| {
+                ApiError::new(ErrorCode::InvalidRequest, e.to_string()).into()
+            }))
+            .app_data(web::QueryConfig::default().error_handler(|e, _| {
+                ApiError::new(ErrorCode::InvalidRequest, e.to_string()).into()
+            }))
             .service(index)
             .service(boot)
             .service(showdown)
+            .service(heartbeat)
             .service(job_request)
             .service(job_submit)
             .service(pool_status)
+            .service(submitter_coverage)
+            .service(reward_balances)
+            .service(submitter_rewards)
+            .service(competition_rounds)
+            .service(competition_round)
+            .service(pool_history)
+            .service(submitter_history)
+            .service(prometheus_metrics)
+            .service(admin::scope())
+            .wrap(rate_limit::RateLimit)
+            .wrap(metrics::Timing)
             .wrap(Logger::default())
     })
-    .bind(("0.0.0.0", 9876))?;
+    .bind(bind_address)?;
     let and = server.run();
-    and.await
+    and.await?;
+    // Stopped by a signal, write out what is only saved periodically.
+    app.flush()
+}
+
+/// Runs `task` on the blocking thread pool every `period` seconds, logging failures as
+/// `what` failed.
+fn spawn_periodic<T: Send + 'static>(
+    data: web::Data<app::ApplicationData>,
+    period: f64,
+    what: &'static str,
+    task: fn(&app::ApplicationData) -> std::io::Result<T>,
+) {
+    let period = std::time::Duration::from_secs_f64(period);
+    actix_web::rt::spawn(async move {
+        let mut ticker = actix_web::rt::time::interval(period);
+        loop {
+            ticker.tick().await;
+            let app = data.clone().into_inner();
+            if let Err(e) = web::block(move || task(&app)).await {
+                eprintln!("{} failed: {}", what, e);
+            }
+        }
+    });
 }
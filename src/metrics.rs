@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{web, Error};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use parking_lot::Mutex;
+
+use crate::app::ApplicationData;
+
+/// Upper bounds of the request duration histogram buckets, in seconds.
+const DURATION_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Observations at most each of `DURATION_BUCKETS`, not cumulative.
+    buckets: [u64; DURATION_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if let Some(i) = DURATION_BUCKETS.iter().position(|&bound| value <= bound) {
+            self.buckets[i] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// Counters and histograms kept since the server started, for `/metrics`. Numbers that can be
+/// read off the pool state, like pending jobs, are gathered when scraped instead.
+#[derive(Debug, Default)]
+pub struct PoolMetrics {
+    /// Job requests by outcome: `issued` or the error code.
+    job_requests: Mutex<BTreeMap<&'static str, u64>>,
+    /// Job submissions by outcome: `processed` or the error code.
+    submissions: Mutex<BTreeMap<&'static str, u64>>,
+    /// Solutions by verdict.
+    solutions: Mutex<BTreeMap<&'static str, u64>>,
+    /// Request durations by method and route pattern.
+    durations: Mutex<BTreeMap<(String, String), Histogram>>,
+}
+
+impl PoolMetrics {
+    pub fn count_job_request(&self, outcome: &'static str) {
+        *self.job_requests.lock().entry(outcome).or_insert(0) += 1;
+    }
+
+    pub fn count_submission(&self, outcome: &'static str) {
+        *self.submissions.lock().entry(outcome).or_insert(0) += 1;
+    }
+
+    pub fn count_solution(&self, verdict: &'static str) {
+        *self.solutions.lock().entry(verdict).or_insert(0) += 1;
+    }
+
+    fn observe_duration(&self, method: &str, route: String, seconds: f64) {
+        self.durations.lock()
+            .entry((String::from(method), route))
+            .or_default()
+            .observe(seconds);
+    }
+
+    /// Writes the counters and histograms in the Prometheus text format.
+    pub fn write(&self, out: &mut String) {
+        write_labeled(
+            out,
+            "hasher_agg_job_requests_total",
+            "Job requests by outcome, issued or an error code.",
+            "outcome",
+            &self.job_requests.lock(),
+        );
+        write_labeled(
+            out,
+            "hasher_agg_submissions_total",
+            "Job submissions by outcome, processed or an error code.",
+            "outcome",
+            &self.submissions.lock(),
+        );
+        write_labeled(
+            out,
+            "hasher_agg_solutions_total",
+            "Submitted solutions by verdict, duplicates included.",
+            "verdict",
+            &self.solutions.lock(),
+        );
+
+        let name = "hasher_agg_request_duration_seconds";
+        header(out, name, "histogram", "Time taken to answer requests, by route.");
+        for ((method, route), histogram) in self.durations.lock().iter() {
+            let labels = format!("method=\"{}\",route=\"{}\"", escape(method), escape(route));
+            let mut cumulative = 0;
+            for (bound, count) in DURATION_BUCKETS.iter().zip(histogram.buckets.iter()) {
+                cumulative += count;
+                let _ = writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, bound, cumulative);
+            }
+            let _ = writeln!(out, "{}_bucket{{{},le=\"+Inf\"}} {}", name, labels, histogram.count);
+            let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, histogram.sum);
+            let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, histogram.count);
+        }
+    }
+}
+
+pub fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn write_labeled(out: &mut String, name: &str, help: &str, label: &str, values: &BTreeMap<&'static str, u64>) {
+    header(out, name, "counter", help);
+    for (value, count) in values.iter() {
+        let _ = writeln!(out, "{}{{{}=\"{}\"}} {}", name, label, escape(value), count);
+    }
+}
+
+/// Escapes a label value.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Middleware timing every request into `PoolMetrics`, labeled with the route pattern so
+/// student numbers in paths do not each get their own series.
+pub struct Timing;
+
+impl<S> Transform<S> for Timing
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Transform = TimingMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(TimingMiddleware { service })
+    }
+}
+
+pub struct TimingMiddleware<S> {
+    service: S,
+}
+
+impl<S> Service for TimingMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<ServiceResponse, Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let start = Instant::now();
+        let response = self.service.call(req);
+        Box::pin(async move {
+            let response = response.await?;
+            let request = response.request();
+            if let Some(app) = request.app_data::<web::Data<ApplicationData>>() {
+                let route = request.match_pattern().unwrap_or_else(|| String::from("unmatched"));
+                app.metrics.observe_duration(request.method().as_str(), route, start.elapsed().as_secs_f64());
+            }
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_and_histograms_are_exposed() {
+        let metrics = PoolMetrics::default();
+        metrics.count_job_request("issued");
+        metrics.count_job_request("issued");
+        metrics.count_submission("no_pending_job");
+        metrics.count_solution("accepted");
+        metrics.observe_duration("POST", String::from("/job/submit"), 0.02);
+        metrics.observe_duration("POST", String::from("/job/submit"), 0.3);
+        metrics.observe_duration("GET", String::from("/\"odd\""), 60.0);
+
+        let mut out = String::new();
+        metrics.write(&mut out);
+        let lines: Vec<&str> = out.lines().collect();
+        for expected in [
+            "# TYPE hasher_agg_job_requests_total counter",
+            "hasher_agg_job_requests_total{outcome=\"issued\"} 2",
+            "hasher_agg_submissions_total{outcome=\"no_pending_job\"} 1",
+            "hasher_agg_solutions_total{verdict=\"accepted\"} 1",
+            "# TYPE hasher_agg_request_duration_seconds histogram",
+            "hasher_agg_request_duration_seconds_bucket{method=\"POST\",route=\"/job/submit\",le=\"0.01\"} 0",
+            "hasher_agg_request_duration_seconds_bucket{method=\"POST\",route=\"/job/submit\",le=\"0.025\"} 1",
+            "hasher_agg_request_duration_seconds_bucket{method=\"POST\",route=\"/job/submit\",le=\"10\"} 2",
+            "hasher_agg_request_duration_seconds_bucket{method=\"POST\",route=\"/job/submit\",le=\"+Inf\"} 2",
+            "hasher_agg_request_duration_seconds_count{method=\"POST\",route=\"/job/submit\"} 2",
+            // Too slow for any bucket but +Inf, and the quotes escaped.
+            "hasher_agg_request_duration_seconds_bucket{method=\"GET\",route=\"/\\\"odd\\\"\",le=\"10\"} 0",
+            "hasher_agg_request_duration_seconds_bucket{method=\"GET\",route=\"/\\\"odd\\\"\",le=\"+Inf\"} 1",
+        ].iter() {
+            assert!(lines.contains(expected), "missing {}", expected);
+        }
+    }
+}
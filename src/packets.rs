@@ -1,6 +1,15 @@
 use serde::Deserialize;
 use serde::Serialize;
 
+use crate::app::BestSolution;
+use crate::bans::{Ban, BanList};
+use crate::competition::{RoundState, RoundStatus};
+use crate::error::ApiError;
+use crate::hashrate::{EffectiveHashrate, SubmitterHashrate};
+use crate::pow::Algorithm;
+use crate::timeseries::{Metrics, Resolution};
+use crate::submitter::Liveness;
+
 /// Send a message informing the cloud the machine is active.
 #[derive(Serialize, Deserialize)]
 pub struct BootRequest {
@@ -20,34 +29,63 @@ pub struct ShutdownRequest {
     pub student_number: String,
 }
 
+/// Sent between requests so the machine is not taken for dead.
+#[derive(Serialize, Deserialize)]
+pub struct HeartbeatRequest {
+    pub student_number: String,
+    pub name: String,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct JobRequestPacket {
     pub student_number: String,
     pub name: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Job {
     pub number: u64,
     pub size: u64,
     pub nounce_start: u64,
     pub nounce_end: u64,
+    /// Leading zero bits a hash needs to count as a share for this job.
+    pub zero_bits: u8,
+    /// Hashed in front of the student number and nounce: `hash(challenge || student_number || nounce)`.
+    pub challenge: String,
+    /// The hash shares are checked with.
+    pub algorithm: Algorithm,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum JobResponsePacket {
     Success(Job),
-    Error(String),
+    Error(ApiError),
 }
 
 /// Solution info 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Solution {
+    /// Hex digest of the job's algorithm. Named from when every job was SHA-256.
     pub sha256: String,
     pub nounce: String,
     pub time: f64,
 }
 
+impl Solution {
+    /// The nounce is a `u64` written in plain decimal digits (no sign, spaces, prefix or
+    /// leading zeros). These are the same characters that follow the student number in the
+    /// hashed buffer, so each nounce has exactly one spelling and one hash.
+    pub fn nounce_value(&self) -> Option<u64> {
+        if self.nounce.is_empty() || !self.nounce.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        if self.nounce.starts_with('0') && self.nounce != "0" {
+            return None;
+        }
+        self.nounce.parse().ok()
+    }
+}
+
 /// When the job is complete, this packet is sent to the pool.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SubmittionPacket {
@@ -59,13 +97,58 @@ pub struct SubmittionPacket {
     pub nounce_start: u64,
     pub nounce_end: u64,
     pub solutions: Vec<Solution>,
+    /// Unix time the packet was signed. Only checked when signatures are required.
+    #[serde(default)]
+    pub timestamp: f64,
+    /// Must be higher than any sequence number the machine sent before, starting at 1. Only
+    /// checked when signatures are required.
+    #[serde(default)]
+    pub sequence: u64,
+}
+
+/// What the server decided about a single solution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SolutionVerdict {
+    Accepted,
+    /// The hash was already submitted by someone.
+    Duplicate,
+    /// The hash has fewer leading zero bits than the job's difficulty.
+    BelowDifficulty,
+    /// The hash is not the hash of the student number and nounce.
+    HashMismatch,
+    /// The hash is not 64 hex characters, or the nounce is not a canonical decimal `u64`.
+    Malformed,
+    /// The nounce lies outside of the leased job range.
+    OutOfRange,
+}
+
+crate::util::wire_names!(SolutionVerdict {
+    Accepted => "accepted",
+    Duplicate => "duplicate",
+    BelowDifficulty => "below_difficulty",
+    HashMismatch => "hash_mismatch",
+    Malformed => "malformed",
+    OutOfRange => "out_of_range",
+});
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SolutionReport {
+    pub nounce: String,
+    pub sha256: String,
+    pub verdict: SolutionVerdict,
+    pub leading_zero_bits: Option<u8>,
 }
 
 /// Received from the server on job submission.
 #[derive(Serialize, Deserialize, Debug)]
-pub enum SubmittionResponsePacket {
-    Accepted,
-    Rejected,
+pub struct SubmittionResponsePacket {
+    pub job_n: u64,
+    /// Part of the job counted as searched, up to the submitted `nounce_end`.
+    pub searched: NounceRange,
+    /// Part of the job past the submitted `nounce_end`, which is handed out again.
+    pub requeued: Option<NounceRange>,
+    /// One report per submitted solution, in the order they were sent.
+    pub solutions: Vec<SolutionReport>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -73,11 +156,272 @@ pub struct PoolStatusRequestPacket {
     pub student_number: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PoolStatusResponsePacket {
+    /// Sum of what the user's machines that are not offline report.
     pub user_total_hash_rate: f64,
+    pub user_hashrate: SubmitterHashrate,
+    /// `user_total_hash_rate` summed over every submitter.
+    pub pool_total_hash_rate: f64,
+    pub pool_effective_hashrate: EffectiveHashrate,
     pub user_total_shares: usize,
     pub pool_total_shares: usize,
+    /// Expected hashes behind the user's accepted shares (2^difficulty per share).
+    pub user_total_work: f64,
+    pub pool_total_work: f64,
     pub pool_best_zero_length: u8,
     pub completed_jobs: u64,
-}
\ No newline at end of file
+}
+/// Half open range of nounces, `[start, end)`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct NounceRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+/// Which parts of a submitter's nounce space have been searched.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CoverageResponsePacket {
+    pub student_number: String,
+    pub next_nounce: u64,
+    pub completed_total: u64,
+    pub completed: Vec<NounceRange>,
+    pub leased: Vec<NounceRange>,
+    pub abandoned: Vec<NounceRange>,
+    /// Ranges below `next_nounce` that were handed out but are not accounted for.
+    pub gaps: Vec<NounceRange>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RewardBalance {
+    pub student_number: String,
+    pub balance: f64,
+}
+
+/// Everyone's reward balance.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RewardsResponsePacket {
+    pub scheme: String,
+    pub round_number: u64,
+    pub round_started: f64,
+    pub balances: Vec<RewardBalance>,
+}
+
+/// One submitter's reward balance and their share of the open round.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SubmitterRewardPacket {
+    pub student_number: String,
+    pub balance: f64,
+    pub round_number: u64,
+    pub current_round_work: f64,
+    /// Fraction of the open round's work done by this submitter.
+    pub current_round_fraction: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LeaderboardEntry {
+    pub student_number: String,
+    pub shares: u64,
+    pub work: f64,
+}
+
+/// A competition round, its best solution and its leaderboard (most work first).
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RoundPacket {
+    pub name: String,
+    pub start: f64,
+    pub end: f64,
+    pub status: RoundStatus,
+    pub algorithm: Algorithm,
+    pub best: Option<BestSolution>,
+    pub leaderboard: Vec<LeaderboardEntry>,
+}
+
+impl RoundPacket {
+    pub fn from_round(round: &RoundState, now: f64) -> Self {
+        RoundPacket {
+            name: round.name.clone(),
+            start: round.start,
+            end: round.end,
+            status: round.status(now),
+            algorithm: round.algorithm.unwrap_or(crate::config::get().algorithm),
+            best: round.best.clone(),
+            leaderboard: round.leaderboard().into_iter()
+                .map(|(student_number, entry)| LeaderboardEntry {
+                    student_number,
+                    shares: entry.shares,
+                    work: entry.work,
+                })
+                .collect(),
+        }
+    }
+
+    /// Leaves banned students off the leaderboard, and out of the best solution.
+    pub fn without_banned(mut self, bans: &BanList) -> Self {
+        self.leaderboard.retain(|entry| bans.get(&entry.student_number).is_none());
+        if self.best.as_ref().is_some_and(|best| bans.get(&best.student_number).is_some()) {
+            self.best = None;
+        }
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AdminMachine {
+    pub name: String,
+    pub liveness: Liveness,
+    /// Unix time of the machine's last request.
+    pub last_seen: f64,
+    pub pending_jobs: usize,
+    pub target_zero_bits: u8,
+    pub accepted_shares_count: u64,
+    pub invalid_shares_count: u64,
+    /// Share of the recent solutions that were invalid, once there are enough to judge.
+    pub recent_invalid_ratio: Option<f64>,
+    /// Set while the machine is quarantined.
+    pub quarantine: Option<Ban>,
+    pub work: f64,
+    pub reported_total_hashrate: f64,
+    pub effective_hashrate: EffectiveHashrate,
+}
+
+/// A submitter as seen from `/admin/submitters`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AdminSubmitter {
+    pub student_number: String,
+    pub banned: Option<Ban>,
+    pub accepted_shares_count: u64,
+    pub invalid_shares_count: u64,
+    pub invalid_ratio: f64,
+    pub work: f64,
+    /// Sum of what the machines that are not offline report.
+    pub reported_total_hashrate: f64,
+    pub effective_hashrate: EffectiveHashrate,
+    pub next_nounce: u64,
+    pub pending_jobs: usize,
+    pub unfinished_jobs: usize,
+    pub machines: Vec<AdminMachine>,
+}
+
+/// A new API key and signing secret from `/admin/credentials/{student_number}`. Neither can
+/// be recovered later.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IssuedCredentials {
+    pub student_number: String,
+    pub key: String,
+    pub secret: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BanRequest {
+    #[serde(default)]
+    pub reason: String,
+}
+
+/// Query string of `/history` and `/history/{student_number}`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HistoryQuery {
+    /// Unix time, a day before `to` if left out.
+    pub from: Option<f64>,
+    /// Unix time, now if left out.
+    pub to: Option<f64>,
+    /// Raw if left out and `from` is within `timeseries_raw_retention`, hourly otherwise.
+    pub resolution: Option<Resolution>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HistoryPoint {
+    pub time: f64,
+    /// Reported hashrate of the machines that were not offline.
+    pub reported_hashrate: f64,
+    /// Hashrate from accepted shares over the 5 minutes before.
+    pub effective_hashrate: f64,
+    /// Accepted shares so far.
+    pub shares: u64,
+    pub work: f64,
+    pub active_machines: u64,
+}
+
+impl HistoryPoint {
+    pub fn new(time: f64, metrics: &Metrics) -> Self {
+        HistoryPoint {
+            time,
+            reported_hashrate: metrics.reported_hashrate,
+            effective_hashrate: metrics.effective_hashrate,
+            shares: metrics.shares,
+            work: metrics.work,
+            active_machines: metrics.active_machines,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HistoryResponsePacket {
+    /// `pool` or a student number.
+    pub series: String,
+    pub resolution: Resolution,
+    /// Oldest first.
+    pub points: Vec<HistoryPoint>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solution(nounce: &str) -> Solution {
+        Solution {
+            sha256: String::new(),
+            nounce: String::from(nounce),
+            time: 0.0,
+        }
+    }
+
+    #[test]
+    fn nounce_value_accepts_only_canonical_decimal() {
+        assert_eq!(solution("0").nounce_value(), Some(0));
+        assert_eq!(solution("5").nounce_value(), Some(5));
+        assert_eq!(solution("18446744073709551615").nounce_value(), Some(u64::MAX));
+        for nounce in ["", "05", "000005", "00", "+5", "-5", " 5", "5 ", "0x5", "1e3", "18446744073709551616"] {
+            assert_eq!(solution(nounce).nounce_value(), None, "{:?}", nounce);
+        }
+    }
+
+    #[test]
+    fn verdicts_are_sent_by_name() {
+        let verdict = SolutionVerdict::BelowDifficulty;
+        assert_eq!(serde_json::to_value(verdict).unwrap(), "below_difficulty");
+        assert_eq!(serde_json::from_value::<SolutionVerdict>("below_difficulty".into()).unwrap(), verdict);
+        assert!(serde_json::from_value::<SolutionVerdict>("BelowDifficulty".into()).is_err());
+    }
+
+    #[test]
+    fn without_banned_hides_banned_students() {
+        let entry = |student_number: &str| LeaderboardEntry {
+            student_number: String::from(student_number),
+            shares: 1,
+            work: 1.0,
+        };
+        let round = RoundPacket {
+            name: String::from("round"),
+            start: 0.0,
+            end: 1.0,
+            status: RoundStatus::Active,
+            algorithm: Algorithm::Sha256,
+            best: Some(BestSolution {
+                student_number: String::from("cheat"),
+                job_number: 0,
+                leading_zero_bit_length: 40,
+                nounce: String::from("1"),
+                hash: String::new(),
+            }),
+            leaderboard: vec![entry("cheat"), entry("fair")],
+        };
+        let mut bans = BanList::default();
+        bans.ban("cheat", "invalid", 0.0);
+
+        let round = round.without_banned(&bans);
+        assert!(round.best.is_none());
+        let students: Vec<&str> = round.leaderboard.iter().map(|entry| entry.student_number.as_str()).collect();
+        assert_eq!(students, ["fair"]);
+    }
+}
@@ -0,0 +1,125 @@
+use sha2::Digest;
+
+/// A proof of work hash. Shares are digests with enough leading zero bits.
+pub trait PowAlgorithm: std::fmt::Debug + Send + Sync {
+    /// Hashes `preimage` to a 32 byte digest.
+    fn digest(&self, preimage: &[u8]) -> Vec<u8>;
+}
+
+#[derive(Debug)]
+pub struct Sha256;
+
+impl PowAlgorithm for Sha256 {
+    fn digest(&self, preimage: &[u8]) -> Vec<u8> {
+        sha2::Sha256::digest(preimage).to_vec()
+    }
+}
+
+/// `sha256(sha256(preimage))`, as used by bitcoin.
+#[derive(Debug)]
+pub struct DoubleSha256;
+
+impl PowAlgorithm for DoubleSha256 {
+    fn digest(&self, preimage: &[u8]) -> Vec<u8> {
+        sha2::Sha256::digest(&sha2::Sha256::digest(preimage)).to_vec()
+    }
+}
+
+#[derive(Debug)]
+pub struct Sha3_256;
+
+impl PowAlgorithm for Sha3_256 {
+    fn digest(&self, preimage: &[u8]) -> Vec<u8> {
+        sha3::Sha3_256::digest(preimage).to_vec()
+    }
+}
+
+/// BLAKE2s with a 256 bit digest.
+#[derive(Debug)]
+pub struct Blake2s;
+
+impl PowAlgorithm for Blake2s {
+    fn digest(&self, preimage: &[u8]) -> Vec<u8> {
+        blake2::Blake2s::digest(preimage).to_vec()
+    }
+}
+
+#[derive(Debug)]
+pub struct Blake3;
+
+impl PowAlgorithm for Blake3 {
+    fn digest(&self, preimage: &[u8]) -> Vec<u8> {
+        blake3::hash(preimage).as_bytes().to_vec()
+    }
+}
+
+/// The algorithms a job can ask for. Sent to clients in the `Job` packet.
+///
+/// Jobs handed out before algorithms could be chosen were all SHA-256, hence the default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Algorithm {
+    #[default]
+    Sha256,
+    DoubleSha256,
+    Sha3_256,
+    Blake2s,
+    Blake3,
+}
+
+crate::util::wire_names!(Algorithm {
+    Sha256 => "sha256",
+    DoubleSha256 => "sha256d",
+    Sha3_256 => "sha3-256",
+    Blake2s => "blake2s",
+    Blake3 => "blake3",
+});
+
+impl Algorithm {
+    pub fn pow(self) -> &'static dyn PowAlgorithm {
+        match self {
+            Algorithm::Sha256 => &Sha256,
+            Algorithm::DoubleSha256 => &DoubleSha256,
+            Algorithm::Sha3_256 => &Sha3_256,
+            Algorithm::Blake2s => &Blake2s,
+            Algorithm::Blake3 => &Blake3,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(algorithm: Algorithm, preimage: &[u8]) -> String {
+        algorithm.pow().digest(preimage).iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn algorithms_match_known_answers() {
+        let vectors = [
+            (Algorithm::Sha256, "", "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+            (Algorithm::Sha256, "abc", "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+            (Algorithm::DoubleSha256, "", "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456"),
+            (Algorithm::DoubleSha256, "abc", "4f8b42c22dd3729b519ba6f68d2da7cc5b2d606d05daed5ad5128cc03e6c6358"),
+            (Algorithm::Sha3_256, "", "a7ffc6f8bf1ed76651c14756a061d662f580ff4de43b49fa82d80a4b80f8434a"),
+            (Algorithm::Sha3_256, "abc", "3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532"),
+            (Algorithm::Blake2s, "", "69217a3079908094e11121d042354a7c1f55b6482ca1a51e1b250dfd1ed0eef9"),
+            (Algorithm::Blake2s, "abc", "508c5e8c327c14e2e1a72ba34eeb452f37458b209ed63a294d999b4c86675982"),
+            (Algorithm::Blake3, "", "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"),
+            (Algorithm::Blake3, "abc", "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"),
+        ];
+        for (algorithm, preimage, expected) in vectors.iter() {
+            assert_eq!(hex(*algorithm, preimage.as_bytes()), *expected, "{} of {:?}", algorithm.name(), preimage);
+        }
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for algorithm in [Algorithm::Sha256, Algorithm::DoubleSha256, Algorithm::Sha3_256, Algorithm::Blake2s, Algorithm::Blake3].iter() {
+            assert_eq!(algorithm.name().parse::<Algorithm>(), Ok(*algorithm));
+            assert_eq!(serde_json::to_value(algorithm).unwrap(), algorithm.name());
+        }
+        assert!("md5".parse::<Algorithm>().is_err());
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::task::{Context, Poll};
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{web, Error, ResponseError};
+use futures::future::{ok, Either, Ready};
+use parking_lot::Mutex;
+
+use crate::app::ApplicationData;
+use crate::error::{ApiError, ErrorCode};
+
+/// Buckets a limiter may hold before idle ones are dropped.
+const MAX_IDLE_BUCKETS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    updated: f64,
+}
+
+/// Token bucket rate limiter: each key may make `burst` requests at once, refilled at `rate`
+/// requests a second.
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+}
+
+impl RateLimiter {
+    /// A limiter with a `rate` of zero lets everything through.
+    pub fn new(rate: f64, burst: f64) -> Self {
+        RateLimiter {
+            rate,
+            burst,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token for `key`. Returns how many seconds until one is available if there is
+    /// none.
+    pub fn check(&self, key: &str, now: f64) -> Result<(), f64> {
+        if self.rate <= 0.0 {
+            return Ok(());
+        }
+        let mut buckets = self.buckets.lock();
+        if buckets.len() >= MAX_IDLE_BUCKETS && !buckets.contains_key(key) {
+            // A bucket that would be full again is the same as no bucket.
+            let (rate, burst) = (self.rate, self.burst);
+            buckets.retain(|_, bucket| bucket.tokens + (now - bucket.updated) * rate < burst);
+        }
+        let bucket = buckets.entry(String::from(key)).or_insert(TokenBucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.tokens = (bucket.tokens + (now - bucket.updated) * self.rate).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err((1.0 - bucket.tokens) / self.rate)
+        }
+    }
+}
+
+/// Middleware limiting requests from each remote IP with `ApplicationData::ip_limiter`.
+pub struct RateLimit;
+
+impl<S> Transform<S> for RateLimit
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error>,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RateLimitMiddleware { service })
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: S,
+}
+
+impl<S> Service for RateLimitMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error>,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<ServiceResponse, Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        // The peer address, not a forwarded header a client could make up.
+        let ip = req.peer_addr().map(|address| address.ip().to_string());
+        let limited = match (req.app_data::<web::Data<ApplicationData>>(), ip) {
+            (Some(app), Some(ip)) => app.ip_limiter.check(&ip, crate::util::get_time()).err(),
+            _ => None,
+        };
+        match limited {
+            None => Either::Left(self.service.call(req)),
+            Some(retry_after) => {
+                let error = ApiError::from(ErrorCode::RateLimited).with_retry_after(retry_after);
+                let response = if req.path() == "/job/request" {
+                    if let Some(app) = req.app_data::<web::Data<ApplicationData>>() {
+                        app.metrics.count_job_request(error.code.name());
+                    }
+                    crate::routes::job_error_response(error)
+                } else {
+                    error.error_response()
+                };
+                Either::Right(ok(req.into_response(response)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_allows_a_burst_then_refills_at_the_rate() {
+        let limiter = RateLimiter::new(2.0, 3.0);
+        for _ in 0..3 {
+            assert_eq!(limiter.check("a", 100.0), Ok(()));
+        }
+        assert_eq!(limiter.check("a", 100.0), Err(0.5));
+        // Other keys have their own bucket.
+        assert_eq!(limiter.check("b", 100.0), Ok(()));
+        assert_eq!(limiter.check("a", 100.25), Err(0.25));
+        assert_eq!(limiter.check("a", 100.5), Ok(()));
+        assert!(limiter.check("a", 100.5).is_err());
+        // A long pause refills no more than the burst.
+        for _ in 0..3 {
+            assert_eq!(limiter.check("a", 1000.0), Ok(()));
+        }
+        assert!(limiter.check("a", 1000.0).is_err());
+    }
+
+    #[test]
+    fn zero_rate_never_limits() {
+        let limiter = RateLimiter::new(0.0, 0.0);
+        for _ in 0..100 {
+            assert_eq!(limiter.check("a", 0.0), Ok(()));
+        }
+    }
+}
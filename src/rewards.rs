@@ -0,0 +1,244 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Decides how a round's reward is split between submitters.
+pub trait RewardScheme: std::fmt::Debug + Send + Sync {
+    fn name(&self) -> &'static str;
+    /// Work credited to each submitter for the round being closed.
+    fn credited_work(&self, ledger: &RewardLedger) -> HashMap<String, f64>;
+}
+
+/// Everyone is paid in proportion to the work they did during the round.
+#[derive(Debug)]
+pub struct Proportional;
+
+impl RewardScheme for Proportional {
+    fn name(&self) -> &'static str {
+        "proportional"
+    }
+
+    fn credited_work(&self, ledger: &RewardLedger) -> HashMap<String, f64> {
+        ledger.round_work.clone()
+    }
+}
+
+/// Pay per last N shares: everyone is paid in proportion to their work among the last N
+/// shares, whichever round those shares were found in. Hopping in just before a round closes
+/// earns no more than steady mining.
+#[derive(Debug)]
+pub struct Pplns {
+    pub window: usize,
+}
+
+impl RewardScheme for Pplns {
+    fn name(&self) -> &'static str {
+        "pplns"
+    }
+
+    fn credited_work(&self, ledger: &RewardLedger) -> HashMap<String, f64> {
+        let mut work = HashMap::new();
+        for share in ledger.recent_shares.iter().rev().take(self.window) {
+            *work.entry(share.student_number.clone()).or_insert(0.0) += share.work;
+        }
+        work
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ShareRecord {
+    pub student_number: String,
+    pub work: f64,
+}
+
+/// Why a round was closed.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum RoundCloseReason {
+    /// A new best solution was found.
+    NewBest,
+    /// `reward_round_interval` seconds passed.
+    Interval,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Payout {
+    pub student_number: String,
+    pub work: f64,
+    pub fraction: f64,
+    pub reward: f64,
+}
+
+/// The result of a closed round.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RewardRound {
+    pub number: u64,
+    pub started: f64,
+    pub ended: f64,
+    pub reason: RoundCloseReason,
+    pub scheme: String,
+    pub total_work: f64,
+    pub payouts: Vec<Payout>,
+}
+
+/// Work done in the current round, the most recent shares, and everyone's balance.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RewardLedger {
+    pub round_number: u64,
+    pub round_started: f64,
+    /// Work per submitter since the round started.
+    pub round_work: HashMap<String, f64>,
+    /// The last shares accepted, newest last. Holds at most `pplns_window` shares.
+    pub recent_shares: VecDeque<ShareRecord>,
+    /// Total reward earned by each submitter over all rounds.
+    pub balances: HashMap<String, f64>,
+}
+
+impl Default for RewardLedger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RewardLedger {
+    pub fn new() -> Self {
+        RewardLedger {
+            round_number: 0,
+            round_started: crate::util::get_time(),
+            round_work: HashMap::new(),
+            recent_shares: VecDeque::new(),
+            balances: HashMap::new(),
+        }
+    }
+
+    pub fn record_share(&mut self, student_number: &str, work: f64, window: usize) {
+        *self.round_work.entry(String::from(student_number)).or_insert(0.0) += work;
+        self.recent_shares.push_back(ShareRecord {
+            student_number: String::from(student_number),
+            work,
+        });
+        while self.recent_shares.len() > window {
+            self.recent_shares.pop_front();
+        }
+    }
+
+    /// True if the round has run for `interval` seconds. An interval of zero never expires.
+    pub fn interval_elapsed(&self, interval: f64, now: f64) -> bool {
+        interval > 0.0 && now - self.round_started >= interval
+    }
+
+    /// Splits `reward` according to `scheme`, adds it to the balances and starts a new round.
+    /// Returns `None` without closing if nobody has any credited work.
+    pub fn close_round(
+        &mut self,
+        scheme: &dyn RewardScheme,
+        reward: f64,
+        reason: RoundCloseReason,
+        now: f64,
+    ) -> Option<RewardRound> {
+        let credited = scheme.credited_work(self);
+        let total_work: f64 = credited.values().sum();
+        if total_work <= 0.0 {
+            return None;
+        }
+        let mut payouts: Vec<Payout> = credited.into_iter()
+            .map(|(student_number, work)| {
+                let fraction = work / total_work;
+                Payout {
+                    student_number,
+                    work,
+                    fraction,
+                    reward: fraction * reward,
+                }
+            })
+            .collect();
+        payouts.sort_by(|a, b| b.work.total_cmp(&a.work));
+        for payout in payouts.iter() {
+            *self.balances.entry(payout.student_number.clone()).or_insert(0.0) += payout.reward;
+        }
+        let round = RewardRound {
+            number: self.round_number,
+            started: self.round_started,
+            ended: now,
+            reason,
+            scheme: String::from(scheme.name()),
+            total_work,
+            payouts,
+        };
+        self.round_number += 1;
+        self.round_started = now;
+        self.round_work.clear();
+        Some(round)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ledger(shares: &[(&str, f64)], window: usize) -> RewardLedger {
+        let mut ledger = RewardLedger::new();
+        for &(student_number, work) in shares {
+            ledger.record_share(student_number, work, window);
+        }
+        ledger
+    }
+
+    fn reward_of(round: &RewardRound, student_number: &str) -> Option<f64> {
+        round.payouts.iter()
+            .find(|payout| payout.student_number == student_number)
+            .map(|payout| payout.reward)
+    }
+
+    #[test]
+    fn proportional_pays_for_the_round_and_starts_a_new_one() {
+        let mut ledger = ledger(&[("a", 3.0), ("b", 1.0), ("a", 4.0)], 10);
+        let round = ledger.close_round(&Proportional, 2.0, RoundCloseReason::NewBest, 50.0).unwrap();
+        assert_eq!(round.number, 0);
+        assert_eq!(round.total_work, 8.0);
+        assert_eq!(round.payouts[0].student_number, "a");
+        assert_eq!(reward_of(&round, "a"), Some(1.75));
+        assert_eq!(reward_of(&round, "b"), Some(0.25));
+        assert_eq!(ledger.balances["a"], 1.75);
+        assert_eq!(ledger.round_number, 1);
+        assert_eq!(ledger.round_started, 50.0);
+
+        // Nothing was done in the new round, so it is not closed.
+        assert!(ledger.close_round(&Proportional, 2.0, RoundCloseReason::Interval, 60.0).is_none());
+        assert_eq!(ledger.round_number, 1);
+    }
+
+    #[test]
+    fn pplns_pays_for_the_last_shares_across_rounds() {
+        let scheme = Pplns { window: 4 };
+        let mut ledger = ledger(&[("a", 1.0), ("a", 1.0), ("a", 1.0)], 4);
+        ledger.close_round(&scheme, 1.0, RoundCloseReason::NewBest, 10.0).unwrap();
+        assert_eq!(ledger.balances["a"], 1.0);
+
+        // Hopping in for one share earns a quarter, the older shares still count.
+        ledger.record_share("b", 1.0, 4);
+        let round = ledger.close_round(&scheme, 1.0, RoundCloseReason::NewBest, 20.0).unwrap();
+        assert_eq!(reward_of(&round, "a"), Some(0.75));
+        assert_eq!(reward_of(&round, "b"), Some(0.25));
+
+        // Only the last `window` shares are kept.
+        for _ in 0..4 {
+            ledger.record_share("b", 1.0, 4);
+        }
+        assert_eq!(ledger.recent_shares.len(), 4);
+        let round = ledger.close_round(&scheme, 1.0, RoundCloseReason::NewBest, 30.0).unwrap();
+        assert_eq!(reward_of(&round, "a"), None);
+        assert_eq!(reward_of(&round, "b"), Some(1.0));
+        assert_eq!(ledger.balances["a"], 1.75);
+        assert_eq!(ledger.balances["b"], 1.25);
+    }
+
+    #[test]
+    fn interval_of_zero_never_elapses() {
+        let mut ledger = RewardLedger::new();
+        ledger.round_started = 100.0;
+        assert!(!ledger.interval_elapsed(0.0, 1e12));
+        assert!(!ledger.interval_elapsed(60.0, 159.0));
+        assert!(ledger.interval_elapsed(60.0, 160.0));
+    }
+}
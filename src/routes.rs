@@ -1,18 +1,35 @@
-use std::sync::{Arc, Mutex};
-use sha2::{Digest, Sha256};
-use actix_web::{web, get, post, HttpResponse, Responder, web::Json};
-use crate::{app::{ApplicationData, HashSubmittion, BestSolution}, packets};
-use crate::submitter::StoredJob;
+use actix_web::{web, get, post, HttpRequest, HttpResponse, Responder, web::Json};
+use crate::{app::{ApplicationData, HashSubmittion, BestSolution, SharedSubmitter}, packets};
+use crate::packets::SolutionVerdict;
+use crate::coverage::IntervalSet;
+use crate::hash_store::parse_hash;
+use crate::rewards::RoundCloseReason;
+use crate::competition::RoundStatus;
+use crate::submitter::{invalid_ratio, share_work};
+use crate::error::{ApiError, ErrorCode};
+use crate::hashrate::{EffectiveHashrate, SubmitterHashrate};
+use crate::timeseries::{Resolution, Series};
+use crate::submitter::{Liveness, Submitter};
+use crate::util::escape_html;
+use std::collections::BTreeMap;
+use std::fmt::Write;
 
-type AppData = web::Data<Arc<Mutex<ApplicationData>>>;
+type AppData = web::Data<ApplicationData>;
 
 #[get("/")]
-pub async fn index(data: AppData) -> impl Responder {
-    let app = data.lock().unwrap();
+pub async fn index(data: AppData) -> Result<HttpResponse, ApiError> {
+    let app = data.into_inner();
+    let body = web::block(move || Ok::<_, ApiError>(render_index(&app))).await?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(body))
+}
 
+/// Every string a client sent is escaped, student numbers and machine names can hold markup.
+fn render_index(app: &ApplicationData) -> String {
     let mut body = String::new();
     body += "<!DOCTYPE html><html><head></head><body>";
-    if let Some(best) = &app.best {
+    if let Some(best) = &app.best() {
         body += &format!("
             <h1>Best Solution</h1>
             <p>Leading zero bits length: <b>{}</b></p>
@@ -21,190 +38,360 @@ pub async fn index(data: AppData) -> impl Responder {
             <p>hash: <b>{}</b></p>
             ",
             best.leading_zero_bit_length,
-            best.student_number,
-            best.nounce,
-            best.hash,
+            escape_html(&best.student_number),
+            escape_html(&best.nounce),
+            escape_html(&best.hash),
         );
     } else {
-        body += &format!("<h1>No Best Solution Yet<h1>");
+        body += "<h1>No Best Solution Yet<h1>";
+    }
+
+    let now = crate::util::get_time();
+    match app.rounds().iter().find(|round| round.status(now) == RoundStatus::Active) {
+        Some(round) => body += &format!(
+            "<p>Round <b>{}</b> ends in {:.0} minutes, hashing with <b>{}</b></p>",
+            escape_html(&round.name),
+            (round.end - now) / 60.0,
+            round.algorithm.unwrap_or(crate::config::get().algorithm).name(),
+        ),
+        None if !app.round_is_open() => body += "<p>No round is running, submissions are closed.</p>",
+        None => (),
     }
 
     body += "<h2>Submitters</h2>";
-    let pool_total_shares: usize = app.submitters.iter()
-        .map(|(_student_number, submitter)| submitter.accepted_shares_count as usize)
+    let submitters = app.all_submitters();
+    let pool_total_shares: usize = submitters.iter()
+        .map(|submitter| submitter.lock().accepted_shares_count as usize)
+        .sum();
+    let pool_total_work: f64 = submitters.iter()
+        .map(|submitter| submitter.lock().work)
         .sum();
 
-    for (student_number, submitter) in app.submitters.iter() {
+    let hashrates: Vec<SubmitterHashrate> = submitters.iter()
+        .map(|submitter| SubmitterHashrate::of(&submitter.lock(), now))
+        .collect();
+    let pool_effective: EffectiveHashrate = hashrates.iter().map(|hashrate| hashrate.effective).sum();
+    body += &format!(
+        "<p>Pool MH/s: reported <b>{:.2}</b>, effective {} (5m / 1h / 24h)</p>",
+        hashrates.iter().fold(0.0, |total, hashrate| total + hashrate.reported) / 1_000_000.0,
+        effective_mhs(&pool_effective),
+    );
+
+    let bans = app.bans();
+    for (submitter, hashrate) in submitters.iter().zip(hashrates.iter()) {
+        let submitter = submitter.lock();
         let user_total_shares = submitter.accepted_shares_count as usize;
         body += &format!("
-            <p>SN: <b>{}</b></p>
-            <p>MH/s: <b>{}</b></p>
-            <p>shares: <b>{}/{}</b></p>
-            <hr />
+            <p>SN: <b>{}</b>{}</p>
+            <p>MH/s: reported <b>{:.2}</b>, effective {} (5m / 1h / 24h)</p>
+            <p>shares: <b>{}/{}</b>, invalid <b>{}</b> (<b>{:.2}%</b>)</p>
+            <p>work: <b>{:.3e}</b> hashes (<b>{:.2}%</b> of pool)</p>
             ",
-            student_number,
-            submitter.user_hash_rate() / 1_000_000.0,
+            escape_html(&submitter.student_number),
+            if bans.get(&submitter.student_number).is_some() { " (banned)" } else { "" },
+            hashrate.reported / 1_000_000.0,
+            effective_mhs(&hashrate.effective),
             user_total_shares,
             pool_total_shares,
+            submitter.invalid_shares_count,
+            invalid_ratio(submitter.accepted_shares_count, submitter.invalid_shares_count) * 100.0,
+            submitter.work,
+            percentage(submitter.work, pool_total_work),
         );
+        for (machine, machine_hashrate) in submitter.machines.iter().zip(hashrate.machines.iter()) {
+            body += &format!("
+            <p>&nbsp;&nbsp;{} ({}): difficulty <b>{}</b> bits, shares <b>{}</b>, invalid <b>{}</b>, work <b>{:.3e}</b>,
+            MH/s reported <b>{:.2}</b>, effective {}</p>
+            ",
+                escape_html(&machine.name),
+                if machine.quarantine.is_some() { "quarantined" } else { machine_hashrate.liveness.name() },
+                machine.target_zero_bits,
+                machine.accepted_shares_count,
+                machine.invalid_shares_count,
+                machine.work,
+                machine_hashrate.reported / 1_000_000.0,
+                effective_mhs(&machine_hashrate.effective),
+            );
+        }
+        body += "<hr />";
     }
     body += "</body>";
-    
-    HttpResponse::Ok()
-    .content_type("text/html")
-    .body(&body)
+    body
+}
+
+fn percentage(part: f64, total: f64) -> f64 {
+    if total > 0.0 { part / total * 100.0 } else { 0.0 }
+}
+
+/// Effective hashrates in MH/s for the index page.
+fn effective_mhs(rate: &EffectiveHashrate) -> String {
+    format!(
+        "<b>{:.2}</b> / <b>{:.2}</b> / <b>{:.2}</b>",
+        rate.last_5m / 1_000_000.0,
+        rate.last_1h / 1_000_000.0,
+        rate.last_24h / 1_000_000.0,
+    )
 }
 
 #[post("/boot")]
-pub async fn boot(data: AppData, boot_request: Json<packets::BootRequest>) -> impl Responder {
-    let mut app = data.lock().unwrap();
-    let submitter = (*app).submitter_from(&boot_request.student_number);
-    let machine = submitter.get_machine(&boot_request.name);
-    machine.online = true;
-    submitter.save();
-    HttpResponse::Ok().json(packets::CommandResponse { ok: true, msg: None })
+pub async fn boot(req: HttpRequest, data: AppData, boot_request: Json<packets::BootRequest>) -> Result<HttpResponse, ApiError> {
+    data.authenticate(&req, &boot_request.student_number)?;
+    let app = data.into_inner();
+    web::block(move || {
+        let shared = app.submitter_from(&boot_request.student_number);
+        let mut submitter = shared.lock();
+        check_room_for_machine(&submitter, &boot_request.name)?;
+        submitter.get_machine(&boot_request.name).touch(crate::util::get_time());
+        app.save_submitter(&submitter)?;
+        Ok::<_, ApiError>(())
+    }).await?;
+    Ok(HttpResponse::Ok().json(packets::CommandResponse { ok: true, msg: None }))
 }
 
 
 #[post("/shutdown")]
-pub async fn showdown(data: AppData, shutdown_request: Json<packets::ShutdownRequest>) -> impl Responder {
-    let mut app = data.lock().unwrap();
-    let submitter = (*app).submitter_from(&shutdown_request.student_number);
-    let machine = submitter.get_machine(&shutdown_request.name);
-    machine.online = false;
-    submitter.save();
-    HttpResponse::Ok().json(packets::CommandResponse { ok: true, msg: None })
+pub async fn showdown(req: HttpRequest, data: AppData, shutdown_request: Json<packets::ShutdownRequest>) -> Result<HttpResponse, ApiError> {
+    data.authenticate(&req, &shutdown_request.student_number)?;
+    let app = data.into_inner();
+    web::block(move || {
+        let shared = app.submitter_from(&shutdown_request.student_number);
+        let mut submitter = shared.lock();
+        check_room_for_machine(&submitter, &shutdown_request.name)?;
+        let machine = submitter.get_machine(&shutdown_request.name);
+        machine.online = false;
+        // Nothing more will be submitted for the machine's jobs.
+        submitter.requeue_pending_of(&shutdown_request.name);
+        app.save_submitter(&submitter)?;
+        Ok::<_, ApiError>(())
+    }).await?;
+    Ok(HttpResponse::Ok().json(packets::CommandResponse { ok: true, msg: None }))
+}
+
+/// Keeps a machine from going stale between requests. Not saved, last seen times change too
+/// often to write each one.
+#[post("/heartbeat")]
+pub async fn heartbeat(req: HttpRequest, data: AppData, heartbeat_request: Json<packets::HeartbeatRequest>) -> Result<HttpResponse, ApiError> {
+    data.authenticate(&req, &heartbeat_request.student_number)?;
+    let app = data.into_inner();
+    web::block(move || {
+        let shared = app.submitter_from(&heartbeat_request.student_number);
+        let mut submitter = shared.lock();
+        check_room_for_machine(&submitter, &heartbeat_request.name)?;
+        submitter.get_machine(&heartbeat_request.name).touch(crate::util::get_time());
+        Ok::<_, ApiError>(())
+    }).await?;
+    Ok(HttpResponse::Ok().json(packets::CommandResponse { ok: true, msg: None }))
 }
 
+/// Refuses a machine the student has no room for, see `max_machines`.
+fn check_room_for_machine(submitter: &Submitter, name: &str) -> Result<(), ApiError> {
+    if !submitter.has_room_for(name) {
+        return Err(ErrorCode::TooManyMachines.into());
+    }
+    Ok(())
+}
+
+/// Errors are sent as `JobResponsePacket::Error` so clients only have one packet to parse, see
+/// `job_error_response`.
 #[post("/job/request")]
-pub async fn job_request(data: AppData, job_request: Json<packets::JobRequestPacket>) -> impl Responder {
-    let mut app = data.lock().unwrap();
-    let submitter = (*app).submitter_from(&job_request.student_number);
-    let job = submitter.next_job(&job_request.name);
-    submitter.save();
-    HttpResponse::Ok().json(packets::JobResponsePacket::Success(job))
+pub async fn job_request(req: HttpRequest, data: AppData, body: web::Bytes) -> impl Responder {
+    let app = data.clone();
+    match request_job(req, data, body).await {
+        Ok(job) => {
+            app.metrics.count_job_request("issued");
+            HttpResponse::Ok().json(packets::JobResponsePacket::Success(job))
+        }
+        Err(e) => {
+            app.metrics.count_job_request(e.code.name());
+            job_error_response(e)
+        }
+    }
+}
+
+/// How every error of `/job/request` is sent, including those of the rate limit middleware.
+pub fn job_error_response(error: ApiError) -> HttpResponse {
+    error.response_builder().json(packets::JobResponsePacket::Error(error))
+}
+
+async fn request_job(req: HttpRequest, data: AppData, body: web::Bytes) -> Result<packets::Job, ApiError> {
+    // Parsed by hand, so a malformed body is answered with a `JobResponsePacket` too.
+    let request: packets::JobRequestPacket = serde_json::from_slice(&body)
+        .map_err(|e| ApiError::new(ErrorCode::InvalidRequest, format!("invalid job request: {}", e)))?;
+    data.authenticate(&req, &request.student_number)?;
+    let machine_key = format!("{}/{}", request.student_number, request.name);
+    if let Err(retry_after) = data.job_limiter.check(&machine_key, crate::util::get_time()) {
+        return Err(ApiError::new(ErrorCode::RateLimited, "too many job requests").with_retry_after(retry_after));
+    }
+    let app = data.into_inner();
+    let job = web::block(move || {
+        app.archive_ended_rounds()?;
+        if !app.round_is_open() {
+            return Err(ErrorCode::NoActiveRound.into());
+        }
+        let challenge = app.next_challenge();
+        let algorithm = app.current_algorithm();
+        let shared = app.submitter_from(&request.student_number);
+        let mut submitter = shared.lock();
+        check_room_for_machine(&submitter, &request.name)?;
+        let machine = submitter.get_machine(&request.name);
+        machine.touch(crate::util::get_time());
+        if machine.quarantine.is_some() {
+            return Err(ErrorCode::Quarantined.into());
+        }
+        submitter.expire_leases();
+        let config = crate::config::get();
+        if submitter.pending_jobs_of(&request.name).count() >= config.max_pending_jobs {
+            // The first lease to run out frees a slot.
+            let first_expiry = submitter.pending_jobs_of(&request.name)
+                .map(|job| job.quote_time + config.job_lease_timeout)
+                .fold(f64::INFINITY, f64::min);
+            let retry_after = first_expiry - crate::util::get_time();
+            return Err(ApiError::from(ErrorCode::TooManyPendingJobs).with_retry_after(retry_after));
+        }
+        let job = submitter.next_job(&request.name, &challenge, algorithm);
+        app.save_submitter(&submitter)?;
+        Ok(job)
+    }).await?;
+    Ok(job)
 }
 
 #[post("/job/submit")]
-pub async fn job_submit(data: AppData, submit_request: Json<packets::SubmittionPacket>) -> impl Responder {
+pub async fn job_submit(req: HttpRequest, data: AppData, body: web::Bytes) -> Result<HttpResponse, ApiError> {
+    let app = data.clone();
+    let result = receive_submission(req, data, body).await;
+    match &result {
+        Ok(_) => app.metrics.count_submission("processed"),
+        Err(e) => app.metrics.count_submission(e.code.name()),
+    }
+    result
+}
 
-    let mut app = data.lock().unwrap();
+async fn receive_submission(req: HttpRequest, data: AppData, body: web::Bytes) -> Result<HttpResponse, ApiError> {
+    // Parsed by hand, the signature is over the raw body.
+    let submit_request: packets::SubmittionPacket = serde_json::from_slice(&body)
+        .map_err(|e| ApiError::new(ErrorCode::InvalidRequest, format!("invalid submission: {}", e)))?;
+    data.authenticate(&req, &submit_request.student_number)?;
+    data.verify_signature(&req, &submit_request.student_number, &body)?;
+    let app = data.into_inner();
+    let response = web::block(move || submit_job(&app, &submit_request)).await?;
+    Ok(HttpResponse::Ok().json(response))
+}
 
-    let pending_job = {
-        let submitter = app.submitter_from(&submit_request.student_number);
-        if let Ok(job) = submitter.pop_pending_job(submit_request.job_n) {
-            job 
-        } else {
-            eprintln!("!/job/submit: no pending job. {}", submit_request.job_n);
-            return HttpResponse::Ok().json(packets::SubmittionResponsePacket::Rejected); // no pending job.
+/// Body of `/job/submit`. Holds the submitter's lock throughout, hashes and writes to storage,
+/// so it runs on the blocking thread pool.
+fn submit_job(
+    app: &ApplicationData,
+    submit_request: &packets::SubmittionPacket,
+) -> Result<packets::SubmittionResponsePacket, ApiError> {
+    let shared = app.submitter_from(&submit_request.student_number);
+    let mut submitter = shared.lock();
+    let machine = submitter.get_machine(&submit_request.name);
+    machine.touch(crate::util::get_time());
+    if machine.quarantine.is_some() {
+        eprintln!("!/job/submit: machine {} of {} is quarantined.", submit_request.name, submit_request.student_number);
+        return Err(ErrorCode::Quarantined.into());
+    }
+
+    // Signed packets must be fresh and newer than the last one from the machine.
+    let config = crate::config::get();
+    if config.require_signature {
+        if (crate::util::get_time() - submit_request.timestamp).abs() > config.signature_max_age {
+            eprintln!("!/job/submit: stale timestamp from {}.", submit_request.student_number);
+            return Err(ErrorCode::StaleTimestamp.into());
+        }
+        let machine = submitter.get_machine(&submit_request.name);
+        if submit_request.sequence <= machine.last_sequence {
+            eprintln!("!/job/submit: replayed sequence {} from {}.", submit_request.sequence, submit_request.student_number);
+            return Err(ErrorCode::ReplayedSequence.into());
         }
-        // Job was pending!
+        machine.last_sequence = submit_request.sequence;
+    }
+
+    app.archive_ended_rounds()?;
+    let pending_job = if let Ok(job) = submitter.pop_pending_job(submit_request.job_n, &submit_request.name) {
+        job
+    } else {
+        eprintln!("!/job/submit: no pending job. {}", submit_request.job_n);
+        return Err(ErrorCode::NoPendingJob.into());
     };
+    // Job was pending!
 
     // Check if the start range is the same as the pending job.
     if pending_job.nounce_start != submit_request.nounce_start {
         eprintln!("invalid nounce_start.");
-        return HttpResponse::Ok().json(packets::SubmittionResponsePacket::Rejected); // invalid nounce_start.
+        // Nothing of the job can be trusted as searched, hand it out again.
+        submitter.requeue(pending_job.nounce_start, pending_job.nounce_end, pending_job.zero_bits);
+        app.save_submitter(&submitter)?;
+        return Err(ErrorCode::InvalidNounceStart.into());
+    }
+
+    if !app.round_is_open() {
+        eprintln!("/job/submit: no competition round is active.");
+        submitter.requeue(pending_job.nounce_start, pending_job.nounce_end, pending_job.zero_bits);
+        app.save_submitter(&submitter)?;
+        return Err(ErrorCode::NoActiveRound.into());
     }
 
-    // Collect valid & in-valid hashes
+    // Give every solution a verdict. Nothing here may fail, or the popped job would be lost
+    // and the hashes taken without credit, so rewards are written after the submitter.
     let mut valid_solutions = Vec::new();
-    let mut sh = Sha256::default();
+    let mut reports = Vec::new();
     for sol in submit_request.solutions.iter() {
-        let buffer = if let Ok(buffer) = hash_to_sha256_buffer(&sol.sha256) { buffer } else {
-            eprintln!("Something is wrong with the hash");
-            continue; // Something is wrong with the hash.
-        };
-        let leading_zero_bits = count_leading_zero_bits(&buffer);
-        // Check is hash length requirement passes
-        if leading_zero_bits < crate::constants::MINIMUN_ZERO_BIT_LENGTH {
-            println!("buffer: {:?}", buffer);
-            eprintln!("hash length requirement failed: {}", leading_zero_bits);
-            continue; // hash length requirement failed.
-        }
-
-        // Check the hash is true.
-        let mut buffer: Vec<u8> = vec![]; // To hash.
-        // Add student number to buffer.
-        let student_number = submit_request.student_number.clone();
-        student_number.chars().for_each(|c| buffer.push(c as u8));
-        // Add Initial nounce to buffer.
-        for c in sol.nounce.chars() {
-            buffer.push(c as u8);
-        }
-        //calc hash
-        sh.update(&buffer);
-        let sha256_buffer = sh.finalize_reset();
-        let hash = sha245_to_string(&sha256_buffer);
-        if !hash.eq(&sol.sha256) {
-            eprintln!("hash is invalid");
-            continue; // hash is invalid.
-        }
-        
-        // Submit the hash.
-        match app.submit_hash(&sol.sha256) {
-            HashSubmittion::Accepted => {
-                valid_solutions.push((leading_zero_bits, sol.clone()));
+        let (verdict, leading_zero_bits) = check_solution(
+            &submit_request.student_number,
+            &pending_job,
+            sol,
+        );
+        let verdict = match verdict {
+            SolutionVerdict::Accepted => match parse_hash(&sol.sha256).map(|hash| app.submit_hash(&hash)) {
+                Some(HashSubmittion::Accepted) => SolutionVerdict::Accepted,
+                Some(HashSubmittion::AlreadyExists) => SolutionVerdict::Duplicate,
+                // `check_solution` took it apart already.
+                None => SolutionVerdict::Malformed,
             },
-            HashSubmittion::AlreadyExists => (),
+            verdict => verdict,
+        };
+        reports.push(packets::SolutionReport {
+            nounce: sol.nounce.clone(),
+            sha256: sol.sha256.clone(),
+            verdict,
+            leading_zero_bits,
+        });
+        app.metrics.count_solution(verdict.name());
+        if verdict != SolutionVerdict::Accepted {
+            eprintln!("/job/submit: solution {} from {}: {:?}", sol.nounce, submit_request.student_number, verdict);
+            continue;
         }
+        let leading_zero_bits = leading_zero_bits.unwrap_or(0);
+        valid_solutions.push((leading_zero_bits, sol.clone()));
+    }
 
-        if let Some(current_best) = &app.best {
-            if leading_zero_bits > current_best.leading_zero_bit_length {
-                let best = BestSolution {
-                    student_number,
-                    job_number: submit_request.job_n,
-                    leading_zero_bit_length: leading_zero_bits,
-                    hash: sol.sha256.clone(),
-                    nounce: sol.nounce.clone(),
-    
-                };
-                app.save_best(best.clone());
-                app.best = Some(best);
-            }
-        } else {
-            let best = BestSolution {
-                student_number,
-                job_number: submit_request.job_n,
-                leading_zero_bit_length: leading_zero_bits,
-                hash: sol.sha256.clone(),
-                nounce: sol.nounce.clone(),
-            };
-            app.save_best(best.clone());
-            app.best = Some(best);
+    // Quarantine machines sending mostly bad solutions.
+    let duplicates = reports.iter().filter(|report| report.verdict == SolutionVerdict::Duplicate).count() as u64;
+    let invalid = (reports.len() - valid_solutions.len()) as u64 - duplicates;
+    submitter.record_verdicts(&submit_request.name, valid_solutions.len() as u64, invalid, duplicates);
+    let ratio = submitter.get_machine(&submit_request.name).recent_invalid_ratio();
+    if let Some(ratio) = ratio {
+        if config.quarantine_invalid_ratio > 0.0 && ratio >= config.quarantine_invalid_ratio {
+            let reason = format!("{:.0}% of recent solutions were invalid", ratio * 100.0);
+            eprintln!("/job/submit: quarantined machine {} of {}: {}", submit_request.name, submit_request.student_number, reason);
+            submitter.quarantine(&submit_request.name, &reason, crate::util::get_time());
         }
     }
 
-    let mut submitter = (*app).submitter_from(&submit_request.student_number);
-
-    // Check if the complete batch was returned.
-    if submit_request.nounce_end < pending_job.nounce_end {
-        // Found uncompleted portion. Added it to rejected jobs to be processed later.
-        let number = submitter.next_job_number;
-        submitter.next_job_number += 1;
-        let nounce_start = submit_request.nounce_end;
-        let nounce_end = pending_job.nounce_end;
-        let size = nounce_end - nounce_start + 1;
-        submitter.unfinished_jobs.push(StoredJob {
-            number,
-            size,
-            nounce_start,
-            nounce_end,
-            quote_time: crate::util::get_time(),
-        });
-    }
+    // Record what was searched and queue anything that was not.
+    let searched_end = submitter.finish_job(&pending_job, submit_request.nounce_end);
 
     // add Solutions.
-    submitter.accepted_shares_count += valid_solutions.len() as u64;
-    for (leading, solution) in valid_solutions.into_iter() {
-        submitter.save_solution(solution, leading);
-    }
+    submitter.credit_shares(&submit_request.name, valid_solutions.len() as u64, pending_job.zero_bits);
+    submitter.get_machine(&submit_request.name)
+        .record_shares(valid_solutions.len() as u64, crate::util::get_time());
 
     // update machine info: thread hashrate.
     let reported_thread_hashrate = submit_request.thread_hashes_per_second;
-    let mut machine = submitter.get_machine(&submit_request.name);
+    let machine = submitter.get_machine(&submit_request.name);
     machine.reported_thread_hashrate_history.push(reported_thread_hashrate);
-    if machine.reported_thread_hashrate_history.len() > 100 {
+    if machine.reported_thread_hashrate_history.len() > config.history_window {
         machine.reported_thread_hashrate_history.remove(0);
     }
     let sum: f64 = machine.reported_thread_hashrate_history.iter().sum();
@@ -213,26 +400,126 @@ pub async fn job_submit(data: AppData, submit_request: Json<packets::SubmittionP
 
     // update machine info: total hashrate.
     let reported_total_hashrate = submit_request.total_hashes_per_second;
-    let mut machine = submitter.get_machine(&submit_request.name);
+    let machine = submitter.get_machine(&submit_request.name);
     machine.reported_total_hashrate_history.push(reported_total_hashrate);
-    if machine.reported_total_hashrate_history.len() > 100 {
+    if machine.reported_total_hashrate_history.len() > config.history_window {
         machine.reported_total_hashrate_history.remove(0);
     }
     let sum: f64 = machine.reported_total_hashrate_history.iter().sum();
     let len = machine.reported_total_hashrate_history.len() as f64;
     machine.reported_total_hashrate = sum / len;
 
-    // Recalculate next job size so that it is one minutes worth of work.
-    let hashes_per_minute = machine.reported_thread_hashrate * 30.0;
-    let next_job_size = max(hashes_per_minute.floor() as u64, 1_000_000);
+    // Recalculate next job size so that it is target_job_duration seconds worth of work.
+    let hashes_per_job = machine.reported_thread_hashrate * config.target_job_duration;
+    let next_job_size = (hashes_per_job.floor() as u64).clamp(1_000_000, config.max_job_size);
     machine.calculated_job_size = next_job_size;
 
-    submitter.save();
-    HttpResponse::Ok().json(packets::SubmittionResponsePacket::Accepted)
+    // A crash must not forget hashes whose shares were credited, or they could be sent again.
+    if !valid_solutions.is_empty() {
+        app.sync_hashes()?;
+    }
+    app.save_submitter(&submitter)?;
+    // A deleted submitter's solution files are gone, do not start them again.
+    if !submitter.deleted {
+        let rewarded = reward_solutions(app, submit_request, pending_job.zero_bits, &valid_solutions);
+        for (leading, solution) in valid_solutions.iter() {
+            app.storage.append_solution(&submit_request.student_number, solution, *leading)?;
+        }
+        // Machine names are the client's to pick, so also look at the student as a whole.
+        if let Some(reason) = submitter.ban_reason() {
+            eprintln!("/job/submit: banned {}: {}", submit_request.student_number, reason);
+            app.ban(&submit_request.student_number, &reason)?;
+        }
+        rewarded?;
+    }
+    Ok(packets::SubmittionResponsePacket {
+        job_n: pending_job.number,
+        searched: packets::NounceRange { start: pending_job.nounce_start, end: searched_end },
+        requeued: if searched_end < pending_job.nounce_end {
+            Some(packets::NounceRange { start: searched_end, end: pending_job.nounce_end })
+        } else {
+            None
+        },
+        solutions: reports,
+    })
 }
 
-fn max (a: u64, b: u64) -> u64 {
-    if a > b { a } else { b }
+/// Counts accepted solutions towards the reward round, the competition round and the best
+/// solution. A failed write does not stop the rest from counting, the first error is returned.
+fn reward_solutions(
+    app: &ApplicationData,
+    submit_request: &packets::SubmittionPacket,
+    zero_bits: u8,
+    solutions: &[(u8, packets::Solution)],
+) -> std::io::Result<()> {
+    let mut result = Ok(());
+    for (leading, sol) in solutions {
+        let solution = BestSolution {
+            student_number: submit_request.student_number.clone(),
+            job_number: submit_request.job_n,
+            leading_zero_bit_length: *leading,
+            hash: sol.sha256.clone(),
+            nounce: sol.nounce.clone(),
+        };
+        app.record_round_share(&submit_request.student_number, share_work(zero_bits), &solution);
+        let rewarded = app.record_reward_share(&submit_request.student_number, share_work(zero_bits))
+            .and_then(|()| app.offer_best(solution))
+            .and_then(|is_best| match is_best && crate::config::get().reward_close_on_best {
+                true => app.close_reward_round(RoundCloseReason::NewBest).map(|_| ()),
+                false => Ok(()),
+            });
+        if let Err(e) = rewarded {
+            eprintln!("/job/submit: could not reward a solution of {}: {}", submit_request.student_number, e);
+            result = result.and(Err(e));
+        }
+    }
+    result
+}
+
+/// Checks a solution against its job, everything except whether the hash was seen before.
+/// Also returns the leading zero bit count when the hash could be parsed.
+fn check_solution(
+    student_number: &str,
+    job: &packets::Job,
+    sol: &packets::Solution,
+) -> (SolutionVerdict, Option<u8>) {
+    let nounce = match sol.nounce_value() {
+        Some(nounce) => nounce,
+        None => return (SolutionVerdict::Malformed, None),
+    };
+    let buffer = match crate::util::decode_hex(&sol.sha256) {
+        Ok(buffer) if buffer.len() == 32 => buffer,
+        _ => return (SolutionVerdict::Malformed, None),
+    };
+    let leading_zero_bits = count_leading_zero_bits(&buffer);
+
+    // Check the nounce lies within the leased range.
+    if nounce < job.nounce_start || nounce >= job.nounce_end {
+        return (SolutionVerdict::OutOfRange, Some(leading_zero_bits));
+    }
+
+    // Check is hash length requirement passes
+    if leading_zero_bits < job.zero_bits {
+        return (SolutionVerdict::BelowDifficulty, Some(leading_zero_bits));
+    }
+
+    // Check the hash is true.
+    let mut buffer: Vec<u8> = vec![]; // To hash.
+    // Add the job's challenge to buffer.
+    buffer.extend_from_slice(job.challenge.as_bytes());
+    // Add student number to buffer.
+    student_number.chars().for_each(|c| buffer.push(c as u8));
+    // Add Initial nounce to buffer.
+    for c in sol.nounce.chars() {
+        buffer.push(c as u8);
+    }
+    //calc hash
+    let digest = job.algorithm.pow().digest(&buffer);
+    let hash = sha245_to_string(&digest);
+    if !hash.eq(&sol.sha256) {
+        return (SolutionVerdict::HashMismatch, Some(leading_zero_bits));
+    }
+    (SolutionVerdict::Accepted, Some(leading_zero_bits))
 }
 
 fn sha245_to_string(sha256_buffer: &[u8]) -> String {
@@ -243,61 +530,66 @@ fn sha245_to_string(sha256_buffer: &[u8]) -> String {
     result
 }
 
+/// Needs no API key, so it only reports on submitters that already exist and never makes one.
 #[post("/status")]
-pub async fn pool_status(data: AppData, status_request: Json<packets::PoolStatusRequestPacket>) -> impl Responder {
-    let mut app = data.lock().unwrap();
-    let submitter = app.submitter_from(&status_request.student_number);
-
-    let user_total_hash_rate = submitter.user_hash_rate();
-    let user_total_shares = submitter.accepted_shares_count as usize;
-    let pool_total_shares = app.submitters.iter()
-        .map(|(_student_number, submitter)| submitter.accepted_shares_count as usize)
-        .sum();
-    let next_job_sum: u64 = app.submitters.iter()
-    .map(|(_student_number, submitter)| {
-        submitter.next_job_number
-    })
-    .sum();
-    let pending_job_sum: u64 =
-        app.submitters.iter()
-        .map(|(_sn, sub)| sub.pending_jobs.len() + sub.unfinished_jobs.len())
-        .map(|v| v as u64)
-        .sum();
+pub async fn pool_status(data: AppData, status_request: Json<packets::PoolStatusRequestPacket>) -> Result<HttpResponse, ApiError> {
+    let student_number = &status_request.student_number;
+    data.check_not_banned(student_number)?;
+    let user = data.get_submitter(student_number)
+        .ok_or_else(|| ApiError::new(ErrorCode::NotFound, format!("no submitter {}", student_number)))?;
+    let app = data.into_inner();
+    let packet = web::block(move || Ok::<_, ApiError>(status_of(&app, &user))).await?;
+    Ok(HttpResponse::Ok().json(packet))
+}
+
+/// Body of `/status`. Locks every submitter, so it runs on the blocking thread pool.
+fn status_of(app: &ApplicationData, user: &SharedSubmitter) -> packets::PoolStatusResponsePacket {
+    let now = crate::util::get_time();
+    let (user_hashrate, user_total_shares, user_total_work) = {
+        let submitter = user.lock();
+        (SubmitterHashrate::of(&submitter, now), submitter.accepted_shares_count as usize, submitter.work)
+    };
+
+    let mut pool_total_shares = 0;
+    let mut pool_total_work = 0.0;
+    let mut pool_total_hash_rate = 0.0;
+    let mut pool_effective = vec![];
+    let mut next_job_sum: u64 = 0;
+    let mut pending_job_sum: u64 = 0;
+    for submitter in app.all_submitters() {
+        let submitter = submitter.lock();
+        pool_total_shares += submitter.accepted_shares_count as usize;
+        pool_total_work += submitter.work;
+        let hashrate = SubmitterHashrate::of(&submitter, now);
+        pool_total_hash_rate += hashrate.reported;
+        pool_effective.push(hashrate.effective);
+        next_job_sum += submitter.next_job_number;
+        pending_job_sum += (submitter.pending_jobs.len() + submitter.unfinished_jobs.len()) as u64;
+    }
     
     let completed_jobs = next_job_sum - pending_job_sum;
     
-    let pool_best_zero_length = if let Some(current_best) = &app.best {
+    let pool_best_zero_length = if let Some(current_best) = &app.best() {
         current_best.leading_zero_bit_length
     } else {
         0
     };
 
-    let packet = packets::PoolStatusResponsePacket {
-        user_total_hash_rate,
+    packets::PoolStatusResponsePacket {
+        user_total_hash_rate: user_hashrate.reported,
+        user_hashrate,
+        pool_total_hash_rate,
+        pool_effective_hashrate: pool_effective.into_iter().sum(),
         user_total_shares,
         pool_total_shares,
+        user_total_work,
+        pool_total_work,
         pool_best_zero_length,
         completed_jobs,
-    };
-    HttpResponse::Ok().json(packet)
-}
-
-
-fn hash_to_sha256_buffer(hash: &String) -> Result<Vec<u8>, ()> {
-    if hash.len() % 2 != 0 {
-        return Err(());
-    }
-    let mut buffer = Vec::new();
-    let mut it = hash.chars();
-    while let (Some(upper), Some(lower)) = (it.next(), it.next()) {
-        let mut upper = upper.to_digit(16).ok_or(())? as u8;
-        upper <<= 4;
-        let lower = lower.to_digit(16).ok_or(())? as u8;
-        buffer.push(upper | lower);
     }
-    Ok(buffer)
 }
 
+
 fn count_leading_zero_bits(buffer: &[u8]) -> u8 {
     let mut leading_zero_bits = 0;
     for byte in buffer {
@@ -340,3 +632,646 @@ fn count_leading_zero_bits(buffer: &[u8]) -> u8 {
     }
     leading_zero_bits
 }
+
+#[get("/coverage/{student_number}")]
+pub async fn submitter_coverage(data: AppData, student_number: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    data.check_not_banned(&student_number)?;
+    let shared = data.get_submitter(&student_number)
+        .ok_or_else(|| ApiError::new(ErrorCode::NotFound, format!("no submitter {}", student_number)))?;
+    let packet = web::block(move || {
+        let submitter = shared.lock();
+        let coverage = &submitter.coverage;
+        let to_ranges = |set: &IntervalSet| -> Vec<packets::NounceRange> {
+            set.ranges().iter()
+                .map(|&(start, end)| packets::NounceRange { start, end })
+                .collect()
+        };
+        Ok::<_, ApiError>(packets::CoverageResponsePacket {
+            student_number: submitter.student_number.clone(),
+            next_nounce: submitter.next_nounce,
+            completed_total: coverage.completed.total(),
+            completed: to_ranges(&coverage.completed),
+            leased: to_ranges(&coverage.leased),
+            abandoned: to_ranges(&coverage.abandoned),
+            gaps: to_ranges(&coverage.gaps(submitter.next_nounce)),
+        })
+    }).await?;
+    Ok(HttpResponse::Ok().json(packet))
+}
+
+#[get("/rewards")]
+pub async fn reward_balances(data: AppData) -> impl Responder {
+    let ledger = data.rewards();
+    let bans = data.bans();
+    let mut balances: Vec<packets::RewardBalance> = ledger.balances.iter()
+        .filter(|(student_number, _)| bans.get(student_number).is_none())
+        .map(|(student_number, &balance)| packets::RewardBalance {
+            student_number: student_number.clone(),
+            balance,
+        })
+        .collect();
+    balances.sort_by(|a, b| b.balance.total_cmp(&a.balance));
+    HttpResponse::Ok().json(packets::RewardsResponsePacket {
+        scheme: String::from(data.reward_scheme_name()),
+        round_number: ledger.round_number,
+        round_started: ledger.round_started,
+        balances,
+    })
+}
+
+#[get("/rewards/{student_number}")]
+pub async fn submitter_rewards(data: AppData, student_number: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    data.check_not_banned(&student_number)?;
+    let ledger = data.rewards();
+    let round_total_work: f64 = ledger.round_work.values().sum();
+    let current_round_work = ledger.round_work.get(student_number.as_str()).copied().unwrap_or(0.0);
+    Ok(HttpResponse::Ok().json(packets::SubmitterRewardPacket {
+        student_number: student_number.clone(),
+        balance: ledger.balances.get(student_number.as_str()).copied().unwrap_or(0.0),
+        round_number: ledger.round_number,
+        current_round_work,
+        current_round_fraction: percentage(current_round_work, round_total_work) / 100.0,
+    }))
+}
+
+#[get("/rounds")]
+pub async fn competition_rounds(data: AppData) -> impl Responder {
+    let now = crate::util::get_time();
+    let bans = data.bans();
+    let rounds: Vec<packets::RoundPacket> = data.rounds().iter()
+        .map(|round| packets::RoundPacket::from_round(round, now).without_banned(&bans))
+        .collect();
+    HttpResponse::Ok().json(rounds)
+}
+
+#[get("/rounds/{name}")]
+pub async fn competition_round(data: AppData, name: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let now = crate::util::get_time();
+    let round = data.round(&name)
+        .ok_or_else(|| ApiError::new(ErrorCode::NotFound, format!("no round {}", name)))?;
+    Ok(HttpResponse::Ok().json(packets::RoundPacket::from_round(&round, now).without_banned(&data.bans())))
+}
+
+#[get("/history")]
+pub async fn pool_history(data: AppData, query: web::Query<packets::HistoryQuery>) -> Result<HttpResponse, ApiError> {
+    history(data, None, query.into_inner()).await
+}
+
+#[get("/history/{student_number}")]
+pub async fn submitter_history(
+    data: AppData,
+    student_number: web::Path<String>,
+    query: web::Query<packets::HistoryQuery>,
+) -> Result<HttpResponse, ApiError> {
+    data.check_not_banned(&student_number)?;
+    history(data, Some(student_number.into_inner()), query.into_inner()).await
+}
+
+/// Points of the pool's time series, or the submitter's if `student_number` is given.
+async fn history(
+    data: AppData,
+    student_number: Option<String>,
+    query: packets::HistoryQuery,
+) -> Result<HttpResponse, ApiError> {
+    let now = crate::util::get_time();
+    let to = query.to.unwrap_or(now).min(now);
+    let from = query.from.unwrap_or(to - 24.0 * 60.0 * 60.0);
+    if from.is_nan() || to.is_nan() || from > to {
+        return Err(ApiError::new(ErrorCode::InvalidRequest, "from must not be after to"));
+    }
+    let resolution = query.resolution.unwrap_or(if from >= now - Resolution::Raw.retention() {
+        Resolution::Raw
+    } else {
+        Resolution::Hourly
+    });
+    // Nothing older is kept, so there are no segments to read for it.
+    let from = from.max(now - resolution.retention());
+    let app = data.into_inner();
+    let response = web::block(move || {
+        let series = match &student_number {
+            Some(student_number) => Series::Submitter(student_number),
+            None => Series::Pool,
+        };
+        let points = app.history(resolution, series, from, to).iter()
+            .map(|(time, metrics)| packets::HistoryPoint::new(*time, metrics))
+            .collect();
+        Ok::<_, ApiError>(packets::HistoryResponsePacket {
+            series: student_number.unwrap_or_else(|| String::from("pool")),
+            resolution,
+            points,
+        })
+    }).await?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Prometheus metrics in the text exposition format.
+#[get("/metrics")]
+pub async fn prometheus_metrics(data: AppData) -> Result<HttpResponse, ApiError> {
+    let app = data.into_inner();
+    let out = web::block(move || Ok::<_, ApiError>(render_metrics(&app))).await?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(out))
+}
+
+fn render_metrics(app: &ApplicationData) -> String {
+    let now = crate::util::get_time();
+    let mut out = String::new();
+    app.metrics.write(&mut out);
+
+    let mut pending_jobs = 0;
+    let mut unfinished_jobs = 0;
+    let mut accepted_shares = 0;
+    let mut machines: BTreeMap<&'static str, u64> = BTreeMap::new();
+    for liveness in [Liveness::Online, Liveness::Stale, Liveness::Offline] {
+        machines.insert(liveness.name(), 0);
+    }
+    for shared in app.all_submitters() {
+        let submitter = shared.lock();
+        pending_jobs += submitter.pending_jobs.len();
+        unfinished_jobs += submitter.unfinished_jobs.len();
+        accepted_shares += submitter.accepted_shares_count;
+        for machine in submitter.machines.iter() {
+            *machines.entry(machine.liveness(now).name()).or_insert(0) += 1;
+        }
+    }
+    let best_zero_bits = app.best().map_or(0, |best| best.leading_zero_bit_length);
+
+    let gauges = [
+        ("hasher_agg_pending_jobs", "Jobs leased to machines and not yet submitted.", pending_jobs as f64),
+        ("hasher_agg_unfinished_jobs", "Jobs waiting to be handed out again.", unfinished_jobs as f64),
+        ("hasher_agg_accepted_shares", "Accepted shares of every submitter, kept across restarts.", accepted_shares as f64),
+        ("hasher_agg_best_zero_bits", "Leading zero bits of the best solution so far.", best_zero_bits as f64),
+    ];
+    for (name, help, value) in gauges.iter() {
+        crate::metrics::header(&mut out, name, "gauge", help);
+        let _ = writeln!(out, "{} {}", name, value);
+    }
+    crate::metrics::header(&mut out, "hasher_agg_machines", "gauge", "Machines by liveness.");
+    for (liveness, count) in machines.iter() {
+        let _ = writeln!(out, "hasher_agg_machines{{liveness=\"{}\"}} {}", liveness, count);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::{test, App};
+    use hmac::{Hmac, Mac, NewMac};
+    use sha2::Sha256;
+
+    use super::*;
+    use crate::credentials::{Credentials, API_KEY_HEADER, SIGNATURE_HEADER};
+    use crate::pow::Algorithm;
+    use crate::storage::{MemoryStorage, Storage};
+
+    const STUDENT: &str = "s1234567";
+    const MACHINE: &str = "m";
+
+    /// A pool on `MemoryStorage` where `STUDENT` has been issued a key and secret, returned
+    /// with it.
+    fn pool(storage: Arc<MemoryStorage>) -> (ApplicationData, String, String) {
+        let mut credentials = Credentials::default();
+        let (key, secret) = credentials.issue(STUDENT);
+        storage.save_credentials(&credentials).unwrap();
+        (ApplicationData::begin(storage), key, secret)
+    }
+
+    fn lease(app: &ApplicationData) -> packets::Job {
+        app.submitter_from(STUDENT).lock().next_job(MACHINE, "challenge", Algorithm::Sha256)
+    }
+
+    fn submission(job: &packets::Job, sequence: u64, solutions: Vec<packets::Solution>) -> packets::SubmittionPacket {
+        packets::SubmittionPacket {
+            job_n: job.number,
+            name: String::from(MACHINE),
+            student_number: String::from(STUDENT),
+            thread_hashes_per_second: 0.0,
+            total_hashes_per_second: 0.0,
+            nounce_start: job.nounce_start,
+            nounce_end: job.nounce_end,
+            solutions,
+            timestamp: crate::util::get_time(),
+            sequence,
+        }
+    }
+
+    fn post(uri: &str, body: &[u8]) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri(uri)
+            .header("content-type", "application/json")
+            .set_payload(body.to_vec())
+    }
+
+    fn job() -> packets::Job {
+        packets::Job {
+            number: 0,
+            size: 10,
+            nounce_start: 10,
+            nounce_end: 20,
+            zero_bits: crate::config::get().min_zero_bits,
+            challenge: String::from("challenge"),
+            algorithm: Algorithm::Sha256,
+        }
+    }
+
+    /// The true hash of `nounce`.
+    fn hash_of(nounce: &str) -> String {
+        let job = job();
+        let buffer = format!("{}{}{}", job.challenge, STUDENT, nounce);
+        sha245_to_string(&job.algorithm.pow().digest(buffer.as_bytes()))
+    }
+
+    fn verdict(nounce: &str, sha256: &str) -> SolutionVerdict {
+        let sol = packets::Solution {
+            sha256: String::from(sha256),
+            nounce: String::from(nounce),
+            time: 0.0,
+        };
+        check_solution(STUDENT, &job(), &sol).0
+    }
+
+    #[test]
+    fn check_solution_gives_each_problem_its_verdict() {
+        // Enough leading zero bits for any difficulty, but not the hash of anything.
+        let zeros = "0".repeat(63) + "1";
+        assert_eq!(verdict("15", &zeros), SolutionVerdict::HashMismatch);
+        assert_eq!(verdict("15", &hash_of("15")), SolutionVerdict::BelowDifficulty);
+        assert_eq!(verdict("9", &zeros), SolutionVerdict::OutOfRange);
+        assert_eq!(verdict("20", &zeros), SolutionVerdict::OutOfRange);
+        assert_eq!(verdict("x", &zeros), SolutionVerdict::Malformed);
+        // The same nounce spelt with a leading zero would hash differently.
+        assert_eq!(verdict("015", &zeros), SolutionVerdict::Malformed);
+        assert_eq!(verdict("15", "00"), SolutionVerdict::Malformed);
+        assert_eq!(verdict("15", &(zeros.clone() + "00")), SolutionVerdict::Malformed);
+    }
+
+    #[test]
+    fn check_solution_hashes_the_challenge() {
+        let hash = hash_of("15");
+        let sol = packets::Solution {
+            sha256: hash,
+            nounce: String::from("15"),
+            time: 0.0,
+        };
+        let at = |challenge: &str| {
+            let job = packets::Job { zero_bits: 0, challenge: String::from(challenge), ..job() };
+            check_solution(STUDENT, &job, &sol).0
+        };
+        assert_eq!(at("challenge"), SolutionVerdict::Accepted);
+        // Work done for one challenge is worth nothing under another.
+        assert_eq!(at("other"), SolutionVerdict::HashMismatch);
+        assert_eq!(at(""), SolutionVerdict::HashMismatch);
+    }
+
+    #[test]
+    fn check_solution_uses_the_jobs_algorithm() {
+        let sol = packets::Solution {
+            sha256: hash_of("15"),
+            nounce: String::from("15"),
+            time: 0.0,
+        };
+        let at = |algorithm| check_solution(STUDENT, &packets::Job { zero_bits: 0, algorithm, ..job() }, &sol).0;
+        assert_eq!(at(Algorithm::Sha256), SolutionVerdict::Accepted);
+        assert_eq!(at(Algorithm::Blake3), SolutionVerdict::HashMismatch);
+    }
+
+    #[test]
+    fn check_solution_holds_shares_to_their_jobs_difficulty() {
+        let hash = hash_of("15");
+        let sol = packets::Solution {
+            sha256: hash.clone(),
+            nounce: String::from("15"),
+            time: 0.0,
+        };
+        let bits = count_leading_zero_bits(&crate::util::decode_hex(&hash).unwrap());
+        let at = |zero_bits| check_solution(STUDENT, &packets::Job { zero_bits, ..job() }, &sol);
+        assert_eq!(at(0), (SolutionVerdict::Accepted, Some(bits)));
+        assert_eq!(at(bits), (SolutionVerdict::Accepted, Some(bits)));
+        assert_eq!(at(bits + 1), (SolutionVerdict::BelowDifficulty, Some(bits)));
+    }
+
+    #[test]
+    fn submit_job_reports_the_requeued_part_of_the_job() {
+        let (app, _, _) = pool(Arc::new(MemoryStorage::new()));
+        let job = lease(&app);
+        let response = submit_job(&app, &submission(&job, 1, vec![])).unwrap();
+        assert_eq!(response.job_n, job.number);
+        assert_eq!((response.searched.start, response.searched.end), (job.nounce_start, job.nounce_end));
+        assert!(response.requeued.is_none());
+
+        let job = lease(&app);
+        let mut packet = submission(&job, 2, vec![]);
+        packet.nounce_end = job.nounce_start + 10;
+        let response = submit_job(&app, &packet).unwrap();
+        assert_eq!(response.searched.end, job.nounce_start + 10);
+        let requeued = response.requeued.unwrap();
+        assert_eq!((requeued.start, requeued.end), (job.nounce_start + 10, job.nounce_end));
+    }
+
+    #[test]
+    fn submit_job_caps_the_next_job_size() {
+        let (app, _, _) = pool(Arc::new(MemoryStorage::new()));
+        let job = lease(&app);
+        let mut packet = submission(&job, 1, vec![]);
+        packet.thread_hashes_per_second = 1e300;
+        submit_job(&app, &packet).unwrap();
+
+        let job = lease(&app);
+        assert_eq!(job.size, crate::config::get().max_job_size);
+        assert_eq!(job.nounce_end - job.nounce_start, job.size);
+    }
+
+    #[test]
+    fn submit_job_rejects_stale_and_replayed_packets() {
+        let (app, _, _) = pool(Arc::new(MemoryStorage::new()));
+        let job = lease(&app);
+        let mut stale = submission(&job, 1, vec![]);
+        stale.timestamp -= 2.0 * crate::config::get().signature_max_age;
+        assert_eq!(submit_job(&app, &stale).unwrap_err().code, ErrorCode::StaleTimestamp);
+
+        submit_job(&app, &submission(&job, 1, vec![])).unwrap();
+        let job = lease(&app);
+        let replayed = submit_job(&app, &submission(&job, 1, vec![])).unwrap_err();
+        assert_eq!(replayed.code, ErrorCode::ReplayedSequence);
+        submit_job(&app, &submission(&job, 2, vec![])).unwrap();
+        let gone = submit_job(&app, &submission(&job, 3, vec![])).unwrap_err();
+        assert_eq!(gone.code, ErrorCode::NoPendingJob);
+    }
+
+    #[test]
+    fn submit_job_holds_jobs_to_the_machine_they_are_leased_to() {
+        let (app, _, _) = pool(Arc::new(MemoryStorage::new()));
+        let first = lease(&app);
+        // The lease runs out and another machine is handed the range.
+        let shared = app.submitter_from(STUDENT);
+        shared.lock().pending_jobs[0].quote_time -= crate::config::get().job_lease_timeout + 1.0;
+        let again = shared.lock().next_job("other", "challenge", Algorithm::Sha256);
+        assert_eq!(again.nounce_start, first.nounce_start);
+        assert_ne!(again.number, first.number);
+
+        let late = submit_job(&app, &submission(&first, 1, vec![])).unwrap_err();
+        assert_eq!(late.code, ErrorCode::NoPendingJob);
+        let stolen = submit_job(&app, &submission(&again, 2, vec![])).unwrap_err();
+        assert_eq!(stolen.code, ErrorCode::NoPendingJob);
+        let mut own = submission(&again, 1, vec![]);
+        own.name = String::from("other");
+        submit_job(&app, &own).unwrap();
+    }
+
+    #[test]
+    fn submit_job_quarantines_the_machine_not_the_student() {
+        let (app, _, _) = pool(Arc::new(MemoryStorage::new()));
+        let window = crate::config::get().quarantine_window;
+        let out_of_range = |job: &packets::Job| packets::Solution {
+            sha256: "0".repeat(63) + "1",
+            nounce: job.nounce_end.to_string(),
+            time: 0.0,
+        };
+
+        let job = lease(&app);
+        submit_job(&app, &submission(&job, 1, vec![out_of_range(&job); window as usize - 1])).unwrap();
+        let job = lease(&app);
+        submit_job(&app, &submission(&job, 2, vec![out_of_range(&job)])).unwrap();
+        let job = lease(&app);
+        let quarantined = submit_job(&app, &submission(&job, 3, vec![])).unwrap_err();
+        assert_eq!(quarantined.code, ErrorCode::Quarantined);
+        assert!(app.check_not_banned(STUDENT).is_ok());
+
+        let shared = app.get_submitter(STUDENT).unwrap();
+        let mut submitter = shared.lock();
+        assert_eq!(submitter.invalid_shares_count, window);
+        assert!(submitter.get_machine(MACHINE).quarantine.is_some());
+        assert!(submitter.get_machine("other").quarantine.is_none());
+        // Lifting the quarantine starts the machine over.
+        assert!(submitter.lift_quarantine(MACHINE));
+        assert_eq!(submitter.get_machine(MACHINE).recent_invalid_ratio(), None);
+    }
+
+    #[test]
+    fn submit_job_bans_students_who_change_machine_names() {
+        let (app, _, _) = pool(Arc::new(MemoryStorage::new()));
+        let config = crate::config::get();
+        let per_machine = config.quarantine_window - 1;
+        let out_of_range = |job: &packets::Job| packets::Solution {
+            sha256: "0".repeat(63) + "1",
+            nounce: job.nounce_end.to_string(),
+            time: 0.0,
+        };
+        // Each machine stays just short of quarantine.
+        let submit_from = |name: &str, sequence: u64| {
+            let job = app.submitter_from(STUDENT).lock().next_job(name, "challenge", Algorithm::Sha256);
+            let mut packet = submission(&job, sequence, vec![out_of_range(&job); per_machine as usize]);
+            packet.name = String::from(name);
+            submit_job(&app, &packet)
+        };
+
+        let machines = config.ban_invalid_shares.div_ceil(per_machine);
+        for i in 0..machines {
+            assert!(app.check_not_banned(STUDENT).is_ok());
+            submit_from(&format!("m{}", i), i + 1).unwrap();
+        }
+        assert_eq!(app.check_not_banned(STUDENT).unwrap_err().code, ErrorCode::Banned);
+        let shared = app.get_submitter(STUDENT).unwrap();
+        assert!(shared.lock().machines.iter().all(|machine| machine.quarantine.is_none()));
+
+        // Unbanning forgives what was sent so far.
+        assert!(app.unban(STUDENT).unwrap());
+        submit_from("again", machines + 1).unwrap();
+        assert!(app.check_not_banned(STUDENT).is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn job_request_caps_pending_jobs_and_request_rate() {
+        let (app, key, _) = pool(Arc::new(MemoryStorage::new()));
+        let config = crate::config::get();
+        for _ in 0..config.max_pending_jobs {
+            lease(&app);
+        }
+        let mut service = test::init_service(
+            App::new()
+                .app_data(web::Data::new(app))
+                .service(job_request),
+        ).await;
+        let request = |name: &str| {
+            let body = serde_json::to_vec(&packets::JobRequestPacket {
+                student_number: String::from(STUDENT),
+                name: String::from(name),
+            }).unwrap();
+            post("/job/request", &body).header(API_KEY_HEADER, key.as_str()).to_request()
+        };
+
+        let error_of = |body: &[u8]| match serde_json::from_slice(body).unwrap() {
+            packets::JobResponsePacket::Error(e) => e,
+            packets::JobResponsePacket::Success(job) => panic!("got {:?}", job),
+        };
+
+        let response = test::call_service(&mut service, request(MACHINE)).await;
+        assert_eq!(response.status(), 429);
+        assert!(response.headers().contains_key("Retry-After"));
+        let error = error_of(&test::read_body(response).await);
+        assert_eq!(error.code, ErrorCode::TooManyPendingJobs);
+        assert!(error.retry_after.is_some());
+
+        // Other machines have their own pending jobs and their own rate.
+        for _ in 0..config.job_rate_burst as usize {
+            let response = test::call_service(&mut service, request("other")).await;
+            assert_eq!(response.status(), 200);
+        }
+        let response = test::call_service(&mut service, request("other")).await;
+        assert_eq!(response.status(), 429);
+        assert_eq!(error_of(&test::read_body(response).await).code, ErrorCode::RateLimited);
+
+        // But new machine names run out.
+        for i in 2..config.max_machines {
+            let response = test::call_service(&mut service, request(&format!("m{}", i))).await;
+            assert_eq!(response.status(), 200);
+        }
+        let response = test::call_service(&mut service, request("one too many")).await;
+        assert_eq!(response.status(), 403);
+        assert_eq!(error_of(&test::read_body(response).await).code, ErrorCode::TooManyMachines);
+    }
+
+    #[actix_rt::test]
+    async fn metrics_count_requests_and_pool_state() {
+        let (app, key, _) = pool(Arc::new(MemoryStorage::new()));
+        lease(&app);
+        let mut service = test::init_service(
+            App::new()
+                .app_data(web::Data::new(app))
+                .wrap(crate::metrics::Timing)
+                .service(job_request)
+                .service(prometheus_metrics),
+        ).await;
+        let body = serde_json::to_vec(&packets::JobRequestPacket {
+            student_number: String::from(STUDENT),
+            name: String::from(MACHINE),
+        }).unwrap();
+        test::call_service(&mut service, post("/job/request", &body).header(API_KEY_HEADER, key.as_str()).to_request()).await;
+        test::call_service(&mut service, post("/job/request", &body).to_request()).await;
+
+        let request = test::TestRequest::get().uri("/metrics").to_request();
+        let response = test::call_service(&mut service, request).await;
+        let out = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        for expected in [
+            "hasher_agg_job_requests_total{outcome=\"issued\"} 1",
+            "hasher_agg_job_requests_total{outcome=\"missing_api_key\"} 1",
+            "hasher_agg_request_duration_seconds_count{method=\"POST\",route=\"/job/request\"} 2",
+            "hasher_agg_pending_jobs 2",
+            "hasher_agg_machines{liveness=\"online\"} 1",
+            "hasher_agg_machines{liveness=\"offline\"} 0",
+        ].iter() {
+            assert!(lines.contains(expected), "missing {} in\n{}", expected, out);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn status_does_not_make_submitters() {
+        let (app, _, _) = pool(Arc::new(MemoryStorage::new()));
+        lease(&app);
+        let app = web::Data::new(app);
+        let mut service = test::init_service(
+            App::new()
+                .app_data(app.clone())
+                .service(pool_status),
+        ).await;
+        let status = |student_number: &str| {
+            let body = serde_json::to_vec(&packets::PoolStatusRequestPacket {
+                student_number: String::from(student_number),
+            }).unwrap();
+            post("/status", &body).to_request()
+        };
+
+        let response = test::call_service(&mut service, status("nobody")).await;
+        assert_eq!(response.status(), 404);
+        let response = test::call_service(&mut service, status(STUDENT)).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(app.all_submitters().len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn job_request_errors_are_job_response_packets() {
+        let (app, _, _) = pool(Arc::new(MemoryStorage::new()));
+        let mut service = test::init_service(
+            App::new()
+                .app_data(web::Data::new(app))
+                .wrap(crate::rate_limit::RateLimit)
+                .service(job_request),
+        ).await;
+        let error_of = |body: &[u8]| match serde_json::from_slice(body).unwrap() {
+            packets::JobResponsePacket::Error(e) => e.code,
+            packets::JobResponsePacket::Success(job) => panic!("got {:?}", job),
+        };
+        let peer = "127.0.0.1:1234".parse().unwrap();
+
+        let response = test::call_service(&mut service, post("/job/request", b"{").peer_addr(peer).to_request()).await;
+        assert_eq!(response.status(), 400);
+        assert_eq!(error_of(&test::read_body(response).await), ErrorCode::InvalidRequest);
+
+        let body = serde_json::to_vec(&packets::JobRequestPacket {
+            student_number: String::from(STUDENT),
+            name: String::from(MACHINE),
+        }).unwrap();
+        let response = test::call_service(&mut service, post("/job/request", &body).peer_addr(peer).to_request()).await;
+        assert_eq!(response.status(), 401);
+        assert_eq!(error_of(&test::read_body(response).await), ErrorCode::MissingApiKey);
+
+        let burst = crate::config::get().ip_rate_burst as usize;
+        let mut last = None;
+        for _ in 0..burst {
+            last = Some(test::call_service(&mut service, post("/job/request", &body).peer_addr(peer).to_request()).await);
+        }
+        let response = last.unwrap();
+        assert_eq!(response.status(), 429);
+        assert_eq!(error_of(&test::read_body(response).await), ErrorCode::RateLimited);
+    }
+
+    #[actix_rt::test]
+    async fn signed_job_is_requested_and_submitted() {
+        let storage = Arc::new(MemoryStorage::new());
+        let (app, key, secret) = pool(storage.clone());
+        let mut service = test::init_service(
+            App::new()
+                .app_data(web::Data::new(app))
+                .service(job_request)
+                .service(job_submit),
+        ).await;
+
+        let body = serde_json::to_vec(&packets::JobRequestPacket {
+            student_number: String::from(STUDENT),
+            name: String::from(MACHINE),
+        }).unwrap();
+        let request = post("/job/request", &body).header(API_KEY_HEADER, key.as_str()).to_request();
+        let job = match test::read_response_json(&mut service, request).await {
+            packets::JobResponsePacket::Success(job) => job,
+            packets::JobResponsePacket::Error(e) => panic!("got {}", e),
+        };
+        let saved = storage.load_submitters();
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].pending_jobs.len(), 1);
+
+        let solution = packets::Solution {
+            sha256: "0".repeat(63) + "1",
+            nounce: job.nounce_start.to_string(),
+            time: 0.0,
+        };
+        let body = serde_json::to_vec(&submission(&job, 1, vec![solution])).unwrap();
+        let unsigned = post("/job/submit", &body).header(API_KEY_HEADER, key.as_str()).to_request();
+        let response = test::call_service(&mut service, unsigned).await;
+        assert_eq!(response.status(), 401);
+
+        let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).unwrap();
+        mac.update(&body);
+        let signature = sha245_to_string(&mac.finalize().into_bytes());
+        let signed = post("/job/submit", &body)
+            .header(API_KEY_HEADER, key.as_str())
+            .header(SIGNATURE_HEADER, signature.as_str())
+            .to_request();
+        let response: packets::SubmittionResponsePacket = test::read_response_json(&mut service, signed).await;
+        assert_eq!(response.solutions[0].verdict, SolutionVerdict::HashMismatch);
+        assert!(storage.load_submitters()[0].pending_jobs.is_empty());
+    }
+}
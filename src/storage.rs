@@ -0,0 +1,623 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::sync::Mutex;
+
+use serde::de::DeserializeOwned;
+
+use crate::app::BestSolution;
+use crate::file_operations::{
+    data_dir,
+    open_append_file,
+    open_overwrite_file,
+    open_read_file,
+    write_atomic,
+    write_atomic_in,
+};
+use crate::competition::RoundState;
+use crate::credentials::Credentials;
+use crate::bans::BanList;
+use crate::packets::{RoundPacket, Solution};
+use crate::rewards::{RewardLedger, RewardRound};
+use crate::submitter::Submitter;
+use crate::timeseries::{Resolution, Sample};
+
+/// Where the pool keeps everything that has to outlive a restart.
+pub trait Storage: std::fmt::Debug + Send + Sync {
+    fn load_best(&self) -> Option<BestSolution>;
+    /// Saves the best solution. `None` clears it.
+    fn save_best(&self, best: Option<&BestSolution>) -> std::io::Result<()>;
+
+    fn load_submitters(&self) -> Vec<Submitter>;
+    fn save_submitter(&self, submitter: &Submitter) -> std::io::Result<()>;
+    /// Removes the submitter and its solutions.
+    fn delete_submitter(&self, student_number: &str) -> std::io::Result<()>;
+    /// Records an accepted solution under its leading zero bit count.
+    fn append_solution(
+        &self,
+        student_number: &str,
+        solution: &Solution,
+        leading_zero_bits: u8,
+    ) -> std::io::Result<()>;
+
+    fn load_rewards(&self) -> Option<RewardLedger>;
+    fn save_rewards(&self, ledger: &RewardLedger) -> std::io::Result<()>;
+    /// Archives the result of a closed reward round.
+    fn append_reward_round(&self, round: &RewardRound) -> std::io::Result<()>;
+
+    fn load_credentials(&self) -> Option<Credentials>;
+    fn save_credentials(&self, credentials: &Credentials) -> std::io::Result<()>;
+
+    fn load_bans(&self) -> Option<BanList>;
+    fn save_bans(&self, bans: &BanList) -> std::io::Result<()>;
+
+    fn load_round(&self, name: &str) -> Option<RoundState>;
+    fn save_round(&self, round: &RoundState) -> std::io::Result<()>;
+    /// Archives the final results of an ended competition round.
+    fn save_round_results(&self, results: &RoundPacket) -> std::io::Result<()>;
+
+    /// Every line of the hash log, in the order written. May hold duplicates.
+    fn load_hashes(&self) -> Vec<String>;
+    /// Appends a hash to the log. It may not be durable until `sync_hashes`.
+    fn append_hash(&self, hash: &str) -> std::io::Result<()>;
+    /// Makes every hash appended so far durable.
+    fn sync_hashes(&self) -> std::io::Result<()>;
+    /// Replaces the hash log with exactly the given hashes. Hashes appended meanwhile may be
+    /// lost with the replaced log.
+    fn rewrite_hashes(&self, hashes: &mut dyn Iterator<Item = String>) -> std::io::Result<()>;
+
+    /// Appends a time series sample to a segment, see `Resolution::segment_of`.
+    fn append_sample(&self, resolution: Resolution, segment: u64, sample: &Sample) -> std::io::Result<()>;
+    /// Samples of a segment in the order written, empty if there is no such segment.
+    fn load_segment(&self, resolution: Resolution, segment: u64) -> Vec<Sample>;
+    fn list_segments(&self, resolution: Resolution) -> Vec<u64>;
+    fn delete_segment(&self, resolution: Resolution, segment: u64) -> std::io::Result<()>;
+}
+
+const HASHES_PATH: &str = "hashes";
+const HASHES_FILE: &str = "hashes.txt";
+
+/// Stores everything as files under the data directory:
+///
+/// - `best/best.json`, `null` once cleared
+/// - `submitters/<student number>/info.json`
+/// - `submitters/<student number>/sol_<leading zero bits>`, one JSON solution per line
+/// - `hashes/hashes.txt`, one hash per line
+/// - `rewards/ledger.json`
+/// - `rewards/rounds.jsonl`, one JSON reward round per line
+/// - `credentials/keys.json`, hashes of the issued API keys
+/// - `bans/bans.json`
+/// - `rounds/<round name>/round.json`, the running state of a competition round
+/// - `rounds/<round name>/results.json`, written once the round has ended
+/// - `timeseries/<resolution>/<segment>.jsonl`, one JSON sample per line
+#[derive(Debug, Default)]
+pub struct FileStorage {
+    hash_file: Mutex<Option<File>>,
+}
+
+impl FileStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Repairs `best.json` and every `info.json` left damaged by a crash or full disk.
+    ///
+    /// Must run before anything is loaded. Returns a description of each repair made.
+    pub fn recover(&self) -> Vec<String> {
+        let mut repairs = vec![];
+        repairs.extend(recover_json::<Option<BestSolution>>("best", "best.json"));
+        repairs.extend(recover_json::<RewardLedger>("rewards", "ledger.json"));
+        repairs.extend(recover_json::<Credentials>("credentials", "keys.json"));
+        repairs.extend(recover_json::<BanList>("bans", "bans.json"));
+        if let Ok(paths) = std::fs::read_dir(format!("{}/rounds", data_dir())) {
+            for path in paths.map_while(Result::ok) {
+                let name = path.file_name().to_string_lossy().into_owned();
+                repairs.extend(recover_json::<RoundState>(&format!("rounds/{}", name), "round.json"));
+            }
+        }
+        let str_path = format!("{}/submitters", data_dir());
+        if let Ok(paths) = std::fs::read_dir(&str_path) {
+            for path in paths.map_while(Result::ok) {
+                let student_number = path.file_name().to_string_lossy().into_owned();
+                repairs.extend(recover_json::<Submitter>(
+                    &format!("submitters/{}", student_number),
+                    "info.json",
+                ));
+            }
+        }
+        repairs
+    }
+}
+
+/// Makes sure `path/filename` holds valid JSON of type `T`, falling back to the `.tmp` copy
+/// of an interrupted write and then the `.bak` copy of the previous write.
+///
+/// A damaged file is renamed to `<filename>.corrupt` so it is not overwritten.
+fn recover_json<T: DeserializeOwned>(path: &str, filename: &str) -> Option<String> {
+    recover_json_in::<T>(&format!("{}/{}", data_dir(), path), path, filename)
+}
+
+/// `recover_json` for a file in `dir`, reported as being in `path`.
+fn recover_json_in<T: DeserializeOwned>(dir: &str, path: &str, filename: &str) -> Option<String> {
+    let current = format!("{}/{}", dir, filename);
+    let tmp = format!("{}.tmp", current);
+    let bak = format!("{}.bak", current);
+    let parses = |file: &str| -> bool {
+        std::fs::read(file)
+            .map(|bytes| serde_json::from_slice::<T>(&bytes).is_ok())
+            .unwrap_or(false)
+    };
+    let exists = |file: &str| std::path::Path::new(file).exists();
+
+    if parses(&current) {
+        // A leftover temporary file is from a write that never got renamed; the current
+        // file is still the last complete one.
+        let _ = std::fs::remove_file(&tmp);
+        return None;
+    }
+    if !exists(&current) && !exists(&tmp) && !exists(&bak) {
+        return None;
+    }
+    let problem = if exists(&current) {
+        // Keep the damaged file for inspection, and out of the way of the `.bak` copy
+        // `write_atomic` makes.
+        let _ = std::fs::rename(&current, format!("{}.corrupt", current));
+        format!("is corrupt (kept as {}.corrupt)", filename)
+    } else {
+        String::from("is missing")
+    };
+    let result = if parses(&tmp) {
+        Some(("the interrupted write", tmp.clone()))
+    } else if parses(&bak) {
+        Some(("the backup", bak.clone()))
+    } else {
+        None
+    };
+    let report = match result {
+        Some((source, file)) => match std::fs::read(&file)
+            .and_then(|bytes| write_atomic_in(dir, filename, &bytes))
+        {
+            Ok(()) => format!("{}/{} {}, restored it from {}", path, filename, problem, source),
+            Err(e) => format!("{}/{} {}, could not restore it from {}: {}", path, filename, problem, source, e),
+        },
+        None => format!("{}/{} {} and no good copy was found", path, filename, problem),
+    };
+    let _ = std::fs::remove_file(&tmp);
+    Some(report)
+}
+
+impl Storage for FileStorage {
+    fn load_best(&self) -> Option<BestSolution> {
+        let file = open_read_file("best", "best.json").ok()?;
+        serde_json::from_reader(&file).ok()?
+    }
+
+    fn save_best(&self, best: Option<&BestSolution>) -> std::io::Result<()> {
+        write_atomic("best", "best.json", &serde_json::to_vec(&best)?)
+    }
+
+    fn load_submitters(&self) -> Vec<Submitter> {
+        let mut submitters = vec![];
+        let str_path = format!("{}/submitters", data_dir());
+        if let Ok(paths) = std::fs::read_dir(&str_path) {
+            for path in paths.map_while(Result::ok) {
+                let student_number = path.file_name().to_string_lossy().into_owned();
+                if let Ok(file) = open_read_file(
+                    &format!("submitters/{}", student_number),
+                    "info.json"
+                ) {
+                    match serde_json::from_reader(file) {
+                        Ok(submitter) => submitters.push(submitter),
+                        Err(e) => eprintln!("Could not read submitters/{}/info.json: {}", student_number, e),
+                    }
+                }
+            }
+        }
+        submitters
+    }
+
+    fn save_submitter(&self, submitter: &Submitter) -> std::io::Result<()> {
+        write_atomic(
+            &format!("submitters/{}", submitter.student_number),
+            "info.json",
+            &serde_json::to_vec(submitter)?,
+        )
+    }
+
+    fn delete_submitter(&self, student_number: &str) -> std::io::Result<()> {
+        match std::fs::remove_dir_all(format!("{}/submitters/{}", data_dir(), student_number)) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    fn append_solution(
+        &self,
+        student_number: &str,
+        solution: &Solution,
+        leading_zero_bits: u8,
+    ) -> std::io::Result<()> {
+        let mut file = open_append_file(
+            &format!("submitters/{}", student_number),
+            &format!("sol_{:02}", leading_zero_bits),
+        )?;
+        serde_json::to_writer(&file, solution)?;
+        writeln!(file)
+    }
+
+    fn load_rewards(&self) -> Option<RewardLedger> {
+        let file = open_read_file("rewards", "ledger.json").ok()?;
+        serde_json::from_reader(&file).ok()
+    }
+
+    fn save_rewards(&self, ledger: &RewardLedger) -> std::io::Result<()> {
+        write_atomic("rewards", "ledger.json", &serde_json::to_vec(ledger)?)
+    }
+
+    fn append_reward_round(&self, round: &RewardRound) -> std::io::Result<()> {
+        let mut file = open_append_file("rewards", "rounds.jsonl")?;
+        serde_json::to_writer(&file, round)?;
+        writeln!(file)
+    }
+
+    fn load_credentials(&self) -> Option<Credentials> {
+        let file = open_read_file("credentials", "keys.json").ok()?;
+        serde_json::from_reader(&file).ok()
+    }
+
+    fn save_credentials(&self, credentials: &Credentials) -> std::io::Result<()> {
+        write_atomic("credentials", "keys.json", &serde_json::to_vec(credentials)?)
+    }
+
+    fn load_bans(&self) -> Option<BanList> {
+        let file = open_read_file("bans", "bans.json").ok()?;
+        serde_json::from_reader(&file).ok()
+    }
+
+    fn save_bans(&self, bans: &BanList) -> std::io::Result<()> {
+        write_atomic("bans", "bans.json", &serde_json::to_vec(bans)?)
+    }
+
+    fn load_round(&self, name: &str) -> Option<RoundState> {
+        let file = open_read_file(&format!("rounds/{}", name), "round.json").ok()?;
+        serde_json::from_reader(&file).ok()
+    }
+
+    fn save_round(&self, round: &RoundState) -> std::io::Result<()> {
+        write_atomic(&format!("rounds/{}", round.name), "round.json", &serde_json::to_vec(round)?)
+    }
+
+    fn save_round_results(&self, results: &RoundPacket) -> std::io::Result<()> {
+        write_atomic(&format!("rounds/{}", results.name), "results.json", &serde_json::to_vec(results)?)
+    }
+
+    fn load_hashes(&self) -> Vec<String> {
+        let mut hashes = vec![];
+        if let Ok(file) = open_read_file(HASHES_PATH, HASHES_FILE) {
+            for line in BufReader::new(file).lines().map_while(Result::ok) {
+                hashes.push(String::from(line.trim()));
+            }
+        }
+        hashes
+    }
+
+    fn append_hash(&self, hash: &str) -> std::io::Result<()> {
+        let mut hash_file = self.hash_file.lock().unwrap();
+        if hash_file.is_none() {
+            *hash_file = Some(open_append_file(HASHES_PATH, HASHES_FILE)?);
+        }
+        writeln!(hash_file.as_mut().unwrap(), "{}", hash)
+    }
+
+    fn sync_hashes(&self) -> std::io::Result<()> {
+        // Synced through a second handle so appends from other submissions need not wait.
+        let file = match &*self.hash_file.lock().unwrap() {
+            Some(file) => file.try_clone()?,
+            // Nothing appended since the log was opened or rewritten, which syncs it.
+            None => return Ok(()),
+        };
+        file.sync_data()
+    }
+
+    fn rewrite_hashes(&self, hashes: &mut dyn Iterator<Item = String>) -> std::io::Result<()> {
+        let tmp_file = format!("{}.tmp", HASHES_FILE);
+        let mut writer = std::io::BufWriter::new(open_overwrite_file(HASHES_PATH, &tmp_file)?);
+        for hash in hashes {
+            writeln!(writer, "{}", hash)?;
+        }
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        let dir = format!("{}/{}", data_dir(), HASHES_PATH);
+        // Appends wait only for the rename, not for the whole rewrite.
+        let mut hash_file = self.hash_file.lock().unwrap();
+        std::fs::rename(
+            format!("{}/{}", dir, tmp_file),
+            format!("{}/{}", dir, HASHES_FILE),
+        )?;
+        // The old handle points at the replaced file.
+        *hash_file = None;
+        Ok(())
+    }
+
+    fn append_sample(&self, resolution: Resolution, segment: u64, sample: &Sample) -> std::io::Result<()> {
+        let mut file = open_append_file(
+            &format!("timeseries/{}", resolution.name()),
+            &format!("{}.jsonl", segment),
+        )?;
+        // One write, so a crash can not leave half a line in front of the next sample.
+        let mut line = serde_json::to_vec(sample)?;
+        line.push(b'\n');
+        file.write_all(&line)
+    }
+
+    fn load_segment(&self, resolution: Resolution, segment: u64) -> Vec<Sample> {
+        let mut samples = vec![];
+        if let Ok(file) = open_read_file(
+            &format!("timeseries/{}", resolution.name()),
+            &format!("{}.jsonl", segment),
+        ) {
+            for line in BufReader::new(file).lines().map_while(Result::ok) {
+                // A line cut short by a crash is skipped.
+                if let Ok(sample) = serde_json::from_str(&line) {
+                    samples.push(sample);
+                }
+            }
+        }
+        samples
+    }
+
+    fn list_segments(&self, resolution: Resolution) -> Vec<u64> {
+        let mut segments = vec![];
+        if let Ok(paths) = std::fs::read_dir(format!("{}/timeseries/{}", data_dir(), resolution.name())) {
+            for path in paths.map_while(Result::ok) {
+                let name = path.file_name().to_string_lossy().into_owned();
+                if let Some(segment) = name.strip_suffix(".jsonl").and_then(|n| n.parse().ok()) {
+                    segments.push(segment);
+                }
+            }
+        }
+        segments
+    }
+
+    fn delete_segment(&self, resolution: Resolution, segment: u64) -> std::io::Result<()> {
+        match std::fs::remove_file(format!("{}/timeseries/{}/{}.jsonl", data_dir(), resolution.name(), segment)) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
+
+/// Keeps everything in memory. Nothing survives a restart.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    inner: Mutex<MemoryStorageInner>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryStorageInner {
+    best: Option<BestSolution>,
+    submitters: HashMap<String, String>,
+    rewards: Option<RewardLedger>,
+    credentials: Option<Credentials>,
+    bans: Option<BanList>,
+    rounds: HashMap<String, RoundState>,
+    hashes: Vec<String>,
+    segments: HashMap<(Resolution, u64), Vec<Sample>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn load_best(&self) -> Option<BestSolution> {
+        self.inner.lock().unwrap().best.clone()
+    }
+
+    fn save_best(&self, best: Option<&BestSolution>) -> std::io::Result<()> {
+        self.inner.lock().unwrap().best = best.cloned();
+        Ok(())
+    }
+
+    fn load_submitters(&self) -> Vec<Submitter> {
+        // Submitters are kept serialized so loading hands back independent copies.
+        self.inner.lock().unwrap().submitters.values()
+            .filter_map(|json| serde_json::from_str(json).ok())
+            .collect()
+    }
+
+    fn save_submitter(&self, submitter: &Submitter) -> std::io::Result<()> {
+        let json = serde_json::to_string(submitter)?;
+        self.inner.lock().unwrap().submitters.insert(submitter.student_number.clone(), json);
+        Ok(())
+    }
+
+    fn delete_submitter(&self, student_number: &str) -> std::io::Result<()> {
+        self.inner.lock().unwrap().submitters.remove(student_number);
+        Ok(())
+    }
+
+    fn append_solution(
+        &self,
+        _student_number: &str,
+        _solution: &Solution,
+        _leading_zero_bits: u8,
+    ) -> std::io::Result<()> {
+        // Solution files are an archive only, nothing reads them back.
+        Ok(())
+    }
+
+    fn load_rewards(&self) -> Option<RewardLedger> {
+        self.inner.lock().unwrap().rewards.clone()
+    }
+
+    fn save_rewards(&self, ledger: &RewardLedger) -> std::io::Result<()> {
+        self.inner.lock().unwrap().rewards = Some(ledger.clone());
+        Ok(())
+    }
+
+    fn append_reward_round(&self, _round: &RewardRound) -> std::io::Result<()> {
+        // Rounds are archived only, balances live in the ledger.
+        Ok(())
+    }
+
+    fn load_credentials(&self) -> Option<Credentials> {
+        self.inner.lock().unwrap().credentials.clone()
+    }
+
+    fn save_credentials(&self, credentials: &Credentials) -> std::io::Result<()> {
+        self.inner.lock().unwrap().credentials = Some(credentials.clone());
+        Ok(())
+    }
+
+    fn load_bans(&self) -> Option<BanList> {
+        self.inner.lock().unwrap().bans.clone()
+    }
+
+    fn save_bans(&self, bans: &BanList) -> std::io::Result<()> {
+        self.inner.lock().unwrap().bans = Some(bans.clone());
+        Ok(())
+    }
+
+    fn load_round(&self, name: &str) -> Option<RoundState> {
+        self.inner.lock().unwrap().rounds.get(name).cloned()
+    }
+
+    fn save_round(&self, round: &RoundState) -> std::io::Result<()> {
+        self.inner.lock().unwrap().rounds.insert(round.name.clone(), round.clone());
+        Ok(())
+    }
+
+    fn save_round_results(&self, _results: &RoundPacket) -> std::io::Result<()> {
+        // The round state already holds everything the results are built from.
+        Ok(())
+    }
+
+    fn load_hashes(&self) -> Vec<String> {
+        self.inner.lock().unwrap().hashes.clone()
+    }
+
+    fn append_hash(&self, hash: &str) -> std::io::Result<()> {
+        self.inner.lock().unwrap().hashes.push(String::from(hash));
+        Ok(())
+    }
+
+    fn sync_hashes(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn rewrite_hashes(&self, hashes: &mut dyn Iterator<Item = String>) -> std::io::Result<()> {
+        self.inner.lock().unwrap().hashes = hashes.collect();
+        Ok(())
+    }
+
+    fn append_sample(&self, resolution: Resolution, segment: u64, sample: &Sample) -> std::io::Result<()> {
+        self.inner.lock().unwrap().segments.entry((resolution, segment)).or_default().push(sample.clone());
+        Ok(())
+    }
+
+    fn load_segment(&self, resolution: Resolution, segment: u64) -> Vec<Sample> {
+        self.inner.lock().unwrap().segments.get(&(resolution, segment)).cloned().unwrap_or_default()
+    }
+
+    fn list_segments(&self, resolution: Resolution) -> Vec<u64> {
+        self.inner.lock().unwrap().segments.keys()
+            .filter(|(r, _)| *r == resolution)
+            .map(|&(_, segment)| segment)
+            .collect()
+    }
+
+    fn delete_segment(&self, resolution: Resolution, segment: u64) -> std::io::Result<()> {
+        self.inner.lock().unwrap().segments.remove(&(resolution, segment));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory of its own for a test.
+    fn test_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("hasher_agg-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.to_string_lossy().into_owned()
+    }
+
+    fn best_json(nounce: &str) -> Vec<u8> {
+        serde_json::to_vec(&BestSolution {
+            student_number: String::from("s"),
+            job_number: 0,
+            leading_zero_bit_length: 40,
+            hash: String::new(),
+            nounce: String::from(nounce),
+        }).unwrap()
+    }
+
+    fn read(dir: &str, filename: &str) -> Option<Vec<u8>> {
+        std::fs::read(format!("{}/{}", dir, filename)).ok()
+    }
+
+    fn recover(dir: &str) -> Option<String> {
+        recover_json_in::<BestSolution>(dir, "best", "best.json")
+    }
+
+    #[test]
+    fn write_atomic_keeps_the_previous_copy() {
+        let dir = test_dir("write-atomic");
+        write_atomic_in(&dir, "best.json", b"first").unwrap();
+        write_atomic_in(&dir, "best.json", b"second").unwrap();
+        assert_eq!(read(&dir, "best.json").unwrap(), b"second");
+        assert_eq!(read(&dir, "best.json.bak").unwrap(), b"first");
+        assert_eq!(read(&dir, "best.json.tmp"), None);
+    }
+
+    #[test]
+    fn recover_prefers_an_interrupted_write_over_the_backup() {
+        let dir = test_dir("recover-tmp");
+        std::fs::write(format!("{}/best.json", dir), b"{\"student_nu").unwrap();
+        std::fs::write(format!("{}/best.json.tmp", dir), best_json("2")).unwrap();
+        std::fs::write(format!("{}/best.json.bak", dir), best_json("1")).unwrap();
+        let report = recover(&dir).unwrap();
+        assert!(report.contains("interrupted write"), "{}", report);
+        assert_eq!(read(&dir, "best.json").unwrap(), best_json("2"));
+        assert_eq!(read(&dir, "best.json.corrupt").unwrap(), b"{\"student_nu");
+        assert_eq!(read(&dir, "best.json.tmp"), None);
+    }
+
+    #[test]
+    fn recover_falls_back_to_the_backup() {
+        let dir = test_dir("recover-bak");
+        std::fs::write(format!("{}/best.json.tmp", dir), b"").unwrap();
+        std::fs::write(format!("{}/best.json.bak", dir), best_json("1")).unwrap();
+        let report = recover(&dir).unwrap();
+        assert!(report.contains("is missing") && report.contains("backup"), "{}", report);
+        assert_eq!(read(&dir, "best.json").unwrap(), best_json("1"));
+    }
+
+    #[test]
+    fn recover_leaves_a_good_file_alone() {
+        let dir = test_dir("recover-good");
+        std::fs::write(format!("{}/best.json", dir), best_json("3")).unwrap();
+        std::fs::write(format!("{}/best.json.tmp", dir), b"half a wri").unwrap();
+        assert_eq!(recover(&dir), None);
+        assert_eq!(read(&dir, "best.json").unwrap(), best_json("3"));
+        assert_eq!(read(&dir, "best.json.tmp"), None);
+
+        let empty = test_dir("recover-empty");
+        assert_eq!(recover(&empty), None);
+    }
+
+    #[test]
+    fn recover_reports_when_nothing_can_be_saved() {
+        let dir = test_dir("recover-lost");
+        std::fs::write(format!("{}/best.json", dir), b"garbage").unwrap();
+        let report = recover(&dir).unwrap();
+        assert!(report.contains("no good copy"), "{}", report);
+        assert_eq!(read(&dir, "best.json"), None);
+        assert_eq!(read(&dir, "best.json.corrupt").unwrap(), b"garbage");
+    }
+}
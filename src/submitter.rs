@@ -1,14 +1,11 @@
 use serde::Deserialize;
 use serde::Serialize;
-use std::path::Path;
 
-use crate::file_operations::{
-    get_cwd,
-    open_read_file,
-    open_overwrite_file,
-    open_append_file,
-};
-use crate::packets::{Job, Solution};
+use crate::bans::Ban;
+use crate::coverage::Coverage;
+use crate::hashrate::WorkHistory;
+use crate::packets::Job;
+use crate::pow::Algorithm;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Machine {
@@ -18,7 +15,146 @@ pub struct Machine {
     pub reported_total_hashrate: f64,
     pub reported_total_hashrate_history: Vec<f64>,
     pub calculated_job_size: u64,
+    /// False after `/shutdown`, until the machine is heard from again.
     pub online: bool,
+    /// Unix time of the machine's last request.
+    #[serde(default)]
+    pub last_seen: f64,
+    /// Leading zero bits a share must have in jobs handed to this machine.
+    #[serde(default = "default_zero_bits")]
+    pub target_zero_bits: u8,
+    /// When the current vardiff window began, zero if it has not.
+    #[serde(default)]
+    pub vardiff_window_start: f64,
+    /// Shares accepted since `vardiff_window_start`.
+    #[serde(default)]
+    pub vardiff_window_shares: u64,
+    #[serde(default)]
+    pub accepted_shares_count: u64,
+    /// Expected hashes behind this machine's accepted shares, see `share_work`.
+    #[serde(default)]
+    pub work: f64,
+    /// Highest sequence number of a signed submission from this machine.
+    #[serde(default)]
+    pub last_sequence: u64,
+    /// Solutions rejected for any reason, duplicates included.
+    #[serde(default)]
+    pub invalid_shares_count: u64,
+    /// Accepted solutions among the recent ones the quarantine check looks at.
+    #[serde(default)]
+    pub recent_accepted: u64,
+    /// Invalid solutions among the recent ones the quarantine check looks at. Duplicates are
+    /// not counted, an honest machine racing another sends them too.
+    #[serde(default)]
+    pub recent_invalid: u64,
+    /// Set while the machine is quarantined for sending mostly invalid solutions.
+    #[serde(default)]
+    pub quarantine: Option<Ban>,
+    /// Work of recent accepted shares, for the effective hashrate.
+    #[serde(default)]
+    pub work_history: WorkHistory,
+}
+
+/// Whether a machine is still working, from how long ago it was last heard from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Liveness {
+    Online,
+    /// Quiet for `machine_stale_after`, its jobs are kept a while longer.
+    Stale,
+    /// Shut down, or quiet for `machine_offline_after`. Its jobs are reclaimed.
+    Offline,
+}
+
+crate::util::wire_names!(Liveness {
+    Online => "online",
+    Stale => "stale",
+    Offline => "offline",
+});
+
+/// Work credited for a share from a job of the given difficulty: the expected number of hashes
+/// needed to find it.
+pub fn share_work(zero_bits: u8) -> f64 {
+    2f64.powi(zero_bits as i32)
+}
+
+fn default_zero_bits() -> u8 {
+    crate::config::get().min_zero_bits
+}
+
+impl Machine {
+    /// Records a request from the machine.
+    pub fn touch(&mut self, now: f64) {
+        self.last_seen = now;
+        self.online = true;
+    }
+
+    pub fn liveness(&self, now: f64) -> Liveness {
+        let config = crate::config::get();
+        let quiet = now - self.last_seen;
+        if !self.online || quiet >= config.machine_offline_after {
+            Liveness::Offline
+        } else if quiet >= config.machine_stale_after {
+            Liveness::Stale
+        } else {
+            Liveness::Online
+        }
+    }
+
+    /// Counts accepted shares towards the vardiff window and retargets the machine's
+    /// difficulty once the window is over, aiming for one share every `target_share_interval`.
+    pub fn record_shares(&mut self, shares: u64, now: f64) {
+        let config = crate::config::get();
+        if self.vardiff_window_start == 0.0 {
+            self.vardiff_window_start = now;
+        }
+        self.vardiff_window_shares += shares;
+        let elapsed = now - self.vardiff_window_start;
+        if elapsed < config.vardiff_window {
+            return;
+        }
+        // Each extra zero bit halves the share rate. A window without shares counts as half a
+        // share so the difficulty still comes down.
+        let expected_shares = elapsed / config.target_share_interval;
+        let observed_shares = (self.vardiff_window_shares as f64).max(0.5);
+        let change = (observed_shares / expected_shares).log2().round() as i32;
+        let zero_bits = (self.target_zero_bits as i32 + change)
+            .clamp(config.min_zero_bits as i32, config.max_zero_bits as i32);
+        self.target_zero_bits = zero_bits as u8;
+        self.vardiff_window_start = now;
+        self.vardiff_window_shares = 0;
+    }
+
+    /// Counts solutions towards the quarantine check, duplicates only towards
+    /// `invalid_shares_count`. Once more than twice `quarantine_window` solutions are counted
+    /// both counts are halved, so old ones fade out.
+    pub fn record_verdicts(&mut self, accepted: u64, invalid: u64, duplicates: u64) {
+        self.invalid_shares_count += invalid + duplicates;
+        self.recent_accepted += accepted;
+        self.recent_invalid += invalid;
+        while self.recent_accepted + self.recent_invalid > 2 * crate::config::get().quarantine_window {
+            self.recent_accepted /= 2;
+            self.recent_invalid /= 2;
+        }
+    }
+
+    /// Share of the recent solutions that were rejected, once there are at least
+    /// `quarantine_window` of them.
+    pub fn recent_invalid_ratio(&self) -> Option<f64> {
+        let recent = self.recent_accepted + self.recent_invalid;
+        if recent < crate::config::get().quarantine_window {
+            return None;
+        }
+        Some(self.recent_invalid as f64 / recent as f64)
+    }
+}
+
+/// Share of `invalid` among all solutions, zero if there are none.
+pub fn invalid_ratio(accepted: u64, invalid: u64) -> f64 {
+    if accepted + invalid == 0 {
+        0.0
+    } else {
+        invalid as f64 / (accepted + invalid) as f64
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -29,41 +165,145 @@ pub struct Submitter {
     pub pending_jobs: Vec<StoredJob>,
     pub unfinished_jobs: Vec<StoredJob>,
     pub accepted_shares_count: u64,
+    /// Expected hashes behind all accepted shares, see `share_work`.
+    #[serde(default)]
+    pub work: f64,
     pub machines: Vec<Machine>,
+    #[serde(default)]
+    pub coverage: Coverage,
+    /// Solutions rejected for any reason, duplicates included.
+    #[serde(default)]
+    pub invalid_shares_count: u64,
+    /// `invalid_shares_count` when the student was last unbanned. Only later invalid
+    /// solutions count towards another ban.
+    #[serde(default)]
+    pub invalid_shares_forgiven: u64,
+    /// Set when the submitter is deleted, so requests still holding it do not write it back.
+    #[serde(skip)]
+    pub deleted: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct StoredJob {
     pub number: u64,
     pub size: u64,
     pub nounce_start: u64,
     pub nounce_end: u64,
     pub quote_time: f64,
+    #[serde(default = "default_zero_bits")]
+    pub zero_bits: u8,
+    /// Empty for jobs handed out before challenges, which hash the bare student number.
+    #[serde(default)]
+    pub challenge: String,
+    #[serde(default)]
+    pub algorithm: Algorithm,
+    /// Machine the job is leased to. Empty while unfinished and for jobs leased before
+    /// machines were recorded.
+    #[serde(default)]
+    pub machine: String,
 }
 
+impl StoredJob {
+    pub fn job(&self) -> Job {
+        Job {
+            number: self.number,
+            nounce_start: self.nounce_start,
+            nounce_end: self.nounce_end,
+            size: self.size,
+            zero_bits: self.zero_bits,
+            challenge: self.challenge.clone(),
+            algorithm: self.algorithm,
+        }
+    }
+}
 
 
 impl Submitter {
     pub fn new(student_number: &str) -> Self {
-        let path = &format!("{}/data/submitters/{}/info.json", get_cwd(), student_number);
-        let open_path = &format!("{}/data/submitters/{}", get_cwd(), student_number);
-        if Path::new(path).exists() {
-            if let Ok(file) = open_read_file(open_path, "info.json") {
-                return serde_json::from_reader(&file).expect("Could not interpret json");
-            }
-        }
-        
-        let submitter = Self {
+        Self {
             machines: vec![],
             next_job_number: 0,
             pending_jobs: vec![],
             unfinished_jobs: vec![],
             accepted_shares_count: 0,
+            work: 0.0,
             next_nounce: 0,
             student_number: String::from(student_number),
-        };
-        submitter.save();
-        submitter
+            coverage: Coverage::default(),
+            invalid_shares_count: 0,
+            invalid_shares_forgiven: 0,
+            deleted: false,
+        }
+    }
+
+    /// Credits shares that predate work accounting. They were all at the minimum difficulty.
+    pub fn migrate(&mut self) {
+        if self.work == 0.0 && self.accepted_shares_count > 0 {
+            self.work = self.accepted_shares_count as f64 * share_work(crate::config::get().min_zero_bits);
+        }
+    }
+
+    /// Credits `shares` accepted shares from a job of difficulty `zero_bits` found by `name`.
+    /// Called for every submission, with no shares too, to keep the work history going.
+    pub fn credit_shares(&mut self, name: &str, shares: u64, zero_bits: u8) {
+        let work = shares as f64 * share_work(zero_bits);
+        self.accepted_shares_count += shares;
+        self.work += work;
+        let machine = self.get_machine(name);
+        machine.accepted_shares_count += shares;
+        machine.work += work;
+        machine.work_history.record(crate::util::get_time(), work);
+    }
+
+    /// Counts the verdicts of a submission from machine `name`. Accepted shares are credited
+    /// separately by `credit_shares`.
+    pub fn record_verdicts(&mut self, name: &str, accepted: u64, invalid: u64, duplicates: u64) {
+        self.invalid_shares_count += invalid + duplicates;
+        self.get_machine(name).record_verdicts(accepted, invalid, duplicates);
+    }
+
+    /// Why the student should be banned, if their machines together sent too many invalid
+    /// solutions. Unlike machine quarantine this can not be dodged by changing machine names.
+    pub fn ban_reason(&self) -> Option<String> {
+        let config = crate::config::get();
+        let invalid = self.invalid_shares_count.saturating_sub(self.invalid_shares_forgiven);
+        if config.ban_invalid_shares == 0 || config.quarantine_invalid_ratio == 0.0 || invalid < config.ban_invalid_shares {
+            return None;
+        }
+        let ratio = invalid_ratio(self.accepted_shares_count, self.invalid_shares_count);
+        if ratio < config.quarantine_invalid_ratio {
+            return None;
+        }
+        Some(format!("{} invalid solutions, {:.0}% of all sent", invalid, ratio * 100.0))
+    }
+
+    /// Quarantines machine `name` and takes back its jobs. Returns how many were taken back.
+    pub fn quarantine(&mut self, name: &str, reason: &str, now: f64) -> usize {
+        self.get_machine(name).quarantine = Some(Ban {
+            since: now,
+            reason: String::from(reason),
+        });
+        self.requeue_pending_of(name)
+    }
+
+    /// Lifts the quarantine of machine `name` and forgets its recent verdicts, so it is not
+    /// quarantined again straight away. Returns false if there is no such quarantined machine.
+    pub fn lift_quarantine(&mut self, name: &str) -> bool {
+        match self.machines.iter_mut().find(|machine| machine.name == name) {
+            Some(machine) if machine.quarantine.is_some() => {
+                machine.quarantine = None;
+                machine.recent_accepted = 0;
+                machine.recent_invalid = 0;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether machine `name` exists, or a new one would stay within `max_machines`.
+    pub fn has_room_for(&self, name: &str) -> bool {
+        self.machines.len() < crate::config::get().max_machines
+            || self.machines.iter().any(|machine| machine.name == name)
     }
 
     /// Returns the machine with the given name. If no machine exists, a new one is made.
@@ -90,6 +330,18 @@ impl Submitter {
                 reported_total_hashrate_history: vec![],
                 calculated_job_size: 1_000_000,
                 online: true,
+                last_seen: crate::util::get_time(),
+                target_zero_bits: default_zero_bits(),
+                vardiff_window_start: 0.0,
+                vardiff_window_shares: 0,
+                accepted_shares_count: 0,
+                work: 0.0,
+                last_sequence: 0,
+                invalid_shares_count: 0,
+                recent_accepted: 0,
+                recent_invalid: 0,
+                quarantine: None,
+                work_history: WorkHistory::default(),
             };
             machines.push(machine);
             machines.last_mut().unwrap()
@@ -97,37 +349,51 @@ impl Submitter {
 
     }
 
-    pub fn next_job(&mut self, name: &str) -> Job {
-        // Check if we need to add old jobs to the unfinished list.
+    /// Moves jobs leased longer than `job_lease_timeout` ago to the unfinished jobs.
+    pub fn expire_leases(&mut self) {
         let mut old_job_indexes = vec![];
         for (i, pending) in self.pending_jobs.iter().enumerate() {
             let age = crate::util::get_time() - pending.quote_time;
-            if age > 10.0*60.0 {
+            if age > crate::config::get().job_lease_timeout {
                 old_job_indexes.push(i);
             }
         }
         // Move old jobs to unfinished.
         for &index in old_job_indexes.iter().rev() {
-            self.unfinished_jobs.push(self.pending_jobs.remove(index));
+            let job = self.pending_jobs.remove(index);
+            self.coverage.abandon(job.nounce_start, job.nounce_end);
+            self.unfinished_jobs.push(job);
         }
+    }
+
+    /// Leases a job to machine `name`. The job is hashed with `algorithm` and `challenge`, even
+    /// if it is an unfinished one handed out again.
+    pub fn next_job(&mut self, name: &str, challenge: &str, algorithm: Algorithm) -> Job {
+        self.expire_leases();
+        let zero_bits = self.get_machine(name).target_zero_bits;
         // If there are jobs that have not been processed, then process them.
-        if let Some(job) = self.unfinished_jobs.pop() {
-            self.pending_jobs.push(job.clone());
-            let job = Job {
-                number: job.number,
-                nounce_start: job.nounce_start,
-                nounce_end: job.nounce_end,
-                size: job.size,
-            };
-            return job;
+        if let Some(mut job) = self.unfinished_jobs.pop() {
+            // A new number, so the machine that held the job before can not submit it.
+            job.number = self.next_job_number;
+            self.next_job_number += 1;
+            job.quote_time = crate::util::get_time();
+            job.zero_bits = zero_bits;
+            job.challenge = String::from(challenge);
+            job.algorithm = algorithm;
+            job.machine = String::from(name);
+            self.coverage.lease(job.nounce_start, job.nounce_end);
+            let leased = job.job();
+            self.pending_jobs.push(job);
+            return leased;
         }
         // Make new job.
         let number = self.next_job_number;
         self.next_job_number += 1;
-        let machine = self.get_machine(name);
-        let size = machine.calculated_job_size;
+        let calculated_size = self.get_machine(name).calculated_job_size;
         let nounce_start = self.next_nounce;
-        let nounce_end = nounce_start + size;
+        // Jobs stop at the end of the nounce space rather than wrap.
+        let nounce_end = nounce_start.saturating_add(calculated_size);
+        let size = nounce_end - nounce_start;
         self.next_nounce = nounce_end;
         let job = StoredJob {
             number,
@@ -135,65 +401,300 @@ impl Submitter {
             nounce_start,
             nounce_end,
             quote_time: crate::util::get_time(),
+            zero_bits,
+            challenge: String::from(challenge),
+            algorithm,
+            machine: String::from(name),
         };
-        self.pending_jobs.push(job.clone());
+        self.coverage.lease(job.nounce_start, job.nounce_end);
+        let leased = job.job();
+        self.pending_jobs.push(job);
 
-        let job = Job {
-            number: job.number,
-            nounce_start: job.nounce_start,
-            nounce_end: job.nounce_end,
-            size: job.size,
-        };
-        return job;
+        leased
     }
 
-    pub fn pop_pending_job(&mut self, number: u64) -> Result<Job, ()> {
+    /// Takes job `number` out of the pending jobs, if it is leased to machine `name`.
+    pub fn pop_pending_job(&mut self, number: u64, name: &str) -> Result<Job, ()> {
         let mut some_index = None;
         for (i, job) in self.pending_jobs.iter().enumerate() {
-            if job.number == number {
+            // Jobs leased before machines were recorded may be submitted by any machine.
+            if job.number == number && (job.machine == name || job.machine.is_empty()) {
                 some_index = Some(i);
                 break;
             }
         }
         if let Some(index) = some_index {
-            let job = self.pending_jobs.remove(index);
-            let job = Job {
-                number: job.number,
-                nounce_start: job.nounce_start,
-                nounce_end: job.nounce_end,
-                size: job.size,
-            };
-            Ok(job)
+            Ok(self.pending_jobs.remove(index).job())
         } else {
             Err(())
         }
     }
 
-    pub fn user_hash_rate(&self) -> f64 {
-        let mut sum = 0.0;
-        for machine in self.machines.iter() {
-            sum += machine.reported_total_hashrate;
+    /// Records that `[job.nounce_start, searched_end)` of a popped job was searched. Anything
+    /// past `searched_end` is queued as an unfinished job. Returns `searched_end` clamped to
+    /// the job.
+    pub fn finish_job(&mut self, job: &Job, searched_end: u64) -> u64 {
+        let searched_end = searched_end.clamp(job.nounce_start, job.nounce_end);
+        self.coverage.complete(job.nounce_start, searched_end);
+        if searched_end < job.nounce_end {
+            // Found uncompleted portion. Added it to rejected jobs to be processed later.
+            self.requeue(searched_end, job.nounce_end, job.zero_bits);
+        }
+        searched_end
+    }
+
+    /// Queues `[nounce_start, nounce_end)` as an unfinished job under a new job number.
+    pub fn requeue(&mut self, nounce_start: u64, nounce_end: u64, zero_bits: u8) {
+        let number = self.next_job_number;
+        self.next_job_number += 1;
+        let size = nounce_end - nounce_start;
+        self.unfinished_jobs.push(StoredJob {
+            number,
+            size,
+            nounce_start,
+            nounce_end,
+            quote_time: crate::util::get_time(),
+            zero_bits,
+            // Replaced when the job is handed out again.
+            challenge: String::new(),
+            algorithm: Algorithm::Sha256,
+            machine: String::new(),
+        });
+        self.coverage.abandon(nounce_start, nounce_end);
+    }
+
+    /// Jobs leased to machine `name`.
+    pub fn pending_jobs_of<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a StoredJob> {
+        self.pending_jobs.iter().filter(move |job| job.machine == name)
+    }
+
+    /// Takes back every leased job, to be handed out again.
+    pub fn requeue_pending(&mut self) -> usize {
+        self.requeue_pending_where(|_| true)
+    }
+
+    /// Takes back the jobs leased to machine `name`.
+    pub fn requeue_pending_of(&mut self, name: &str) -> usize {
+        self.requeue_pending_where(|job| job.machine == name)
+    }
+
+    /// Takes back the jobs leased to machines that are offline. Returns how many there were.
+    pub fn reclaim_offline(&mut self, now: f64) -> usize {
+        let offline: Vec<String> = self.machines.iter()
+            .filter(|machine| machine.liveness(now) == Liveness::Offline)
+            .map(|machine| machine.name.clone())
+            .collect();
+        self.requeue_pending_where(|job| offline.contains(&job.machine))
+    }
+
+    fn requeue_pending_where(&mut self, reclaim: impl Fn(&StoredJob) -> bool) -> usize {
+        let (reclaimed, kept): (Vec<StoredJob>, Vec<StoredJob>) = std::mem::take(&mut self.pending_jobs)
+            .into_iter()
+            .partition(|job| reclaim(job));
+        self.pending_jobs = kept;
+        let count = reclaimed.len();
+        for job in reclaimed {
+            self.coverage.abandon(job.nounce_start, job.nounce_end);
+            self.unfinished_jobs.push(job);
         }
-        sum / self.machines.len() as f64
+        count
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A machine at `zero_bits` whose vardiff window started at time 1000.
+    fn machine(zero_bits: u8) -> Machine {
+        let mut submitter = Submitter::new("s");
+        let machine = submitter.get_machine("m");
+        machine.target_zero_bits = zero_bits;
+        machine.record_shares(0, 1000.0);
+        machine.clone()
+    }
+
+    #[test]
+    fn vardiff_retargets_towards_the_share_interval() {
+        let config = crate::config::get();
+        let window_end = 1000.0 + config.vardiff_window;
+        let expected = config.vardiff_window / config.target_share_interval;
+
+        // Four times the expected shares is two bits harder.
+        let mut fast = machine(40);
+        fast.record_shares(2 * expected as u64, window_end - 1.0);
+        assert_eq!(fast.target_zero_bits, 40);
+        fast.record_shares(2 * expected as u64, window_end);
+        assert_eq!(fast.target_zero_bits, 42);
+        assert_eq!(fast.vardiff_window_shares, 0);
+
+        let mut steady = machine(40);
+        steady.record_shares(expected as u64, window_end);
+        assert_eq!(steady.target_zero_bits, 40);
+
+        // A window without shares still brings the difficulty down.
+        let mut idle = machine(40);
+        idle.record_shares(0, window_end);
+        assert!(idle.target_zero_bits < 40);
+    }
+
+    #[test]
+    fn vardiff_stays_within_the_configured_bits() {
+        let config = crate::config::get();
+        let window_end = 1000.0 + config.vardiff_window;
+        let mut idle = machine(config.min_zero_bits);
+        idle.record_shares(0, window_end);
+        assert_eq!(idle.target_zero_bits, config.min_zero_bits);
+
+        let mut flooding = machine(config.max_zero_bits);
+        flooding.record_shares(1_000_000, window_end);
+        assert_eq!(flooding.target_zero_bits, config.max_zero_bits);
+    }
+
+    #[test]
+    fn jobs_carry_the_machines_difficulty() {
+        let mut submitter = Submitter::new("s");
+        submitter.get_machine("m").target_zero_bits = 40;
+        assert_eq!(submitter.next_job("m", "c", Algorithm::Sha256).zero_bits, 40);
+        assert_eq!(submitter.next_job("other", "c", Algorithm::Sha256).zero_bits, crate::config::get().min_zero_bits);
+    }
+
+    #[test]
+    fn unfinished_jobs_take_the_current_challenge() {
+        let mut submitter = Submitter::new("s");
+        let first = submitter.next_job("m", "old", Algorithm::Sha256);
+        assert_eq!(first.challenge, "old");
+        submitter.pending_jobs[0].quote_time -= crate::config::get().job_lease_timeout + 1.0;
+        let again = submitter.next_job("m", "new", Algorithm::Sha256);
+        assert_eq!(again.nounce_start, first.nounce_start);
+        assert_ne!(again.number, first.number);
+        assert_eq!(again.challenge, "new");
+        assert_eq!(submitter.pending_jobs.len(), 1);
+    }
+
+    #[test]
+    fn shares_are_credited_by_difficulty() {
+        let mut submitter = Submitter::new("s");
+        submitter.credit_shares("a", 2, 40);
+        submitter.credit_shares("b", 1, 42);
+        assert_eq!(submitter.accepted_shares_count, 3);
+        assert_eq!(submitter.work, 6.0 * share_work(40));
+        let machine = submitter.get_machine("b");
+        assert_eq!(machine.accepted_shares_count, 1);
+        assert_eq!(machine.work, 4.0 * share_work(40));
+
+        // Shares from before work was credited were all at the minimum difficulty.
+        let mut old = Submitter::new("s");
+        old.accepted_shares_count = 3;
+        old.migrate();
+        assert_eq!(old.work, 3.0 * share_work(crate::config::get().min_zero_bits));
+        old.migrate();
+        assert_eq!(old.work, 3.0 * share_work(crate::config::get().min_zero_bits));
+    }
+
+    #[test]
+    fn recent_verdicts_fade_out() {
+        let window = crate::config::get().quarantine_window;
+        let mut submitter = Submitter::new("s");
+        submitter.record_verdicts("m", 0, window - 1, 0);
+        assert_eq!(submitter.get_machine("m").recent_invalid_ratio(), None);
+        submitter.record_verdicts("m", window, 1, 0);
+        assert_eq!(submitter.get_machine("m").recent_invalid_ratio(), Some(0.5));
+        assert_eq!(submitter.invalid_shares_count, window);
+
+        // Past twice the window both counts are halved, keeping the ratio.
+        submitter.record_verdicts("m", 2, 0, 0);
+        let machine = submitter.get_machine("m");
+        assert_eq!((machine.recent_accepted, machine.recent_invalid), (window / 2 + 1, window / 2));
+
+        submitter.quarantine("m", "invalid", 10.0);
+        assert!(submitter.lift_quarantine("m"));
+        assert_eq!(submitter.get_machine("m").recent_invalid_ratio(), None);
+        assert_eq!(invalid_ratio(1, 3), 0.75);
+        assert_eq!(invalid_ratio(0, 0), 0.0);
+    }
+
+    #[test]
+    fn liveness_follows_the_last_request() {
+        let config = crate::config::get();
+        let mut machine = machine(40);
+        machine.touch(1000.0);
+        assert_eq!(machine.liveness(1000.0), Liveness::Online);
+        assert_eq!(machine.liveness(1000.0 + config.machine_stale_after), Liveness::Stale);
+        assert_eq!(machine.liveness(1000.0 + config.machine_offline_after), Liveness::Offline);
+        machine.online = false;
+        assert_eq!(machine.liveness(1000.0), Liveness::Offline);
+        machine.touch(1001.0);
+        assert_eq!(machine.liveness(1001.0), Liveness::Online);
+    }
+
+    #[test]
+    fn offline_machines_lose_their_jobs() {
+        let config = crate::config::get();
+        let mut submitter = Submitter::new("s");
+        let gone = submitter.next_job("gone", "c", Algorithm::Sha256);
+        submitter.next_job("here", "c", Algorithm::Sha256);
+        let now = crate::util::get_time();
+        submitter.get_machine("gone").touch(now - config.machine_offline_after);
+        submitter.get_machine("here").touch(now);
+
+        assert_eq!(submitter.reclaim_offline(now), 1);
+        assert_eq!(submitter.pending_jobs_of("gone").count(), 0);
+        assert_eq!(submitter.pending_jobs_of("here").count(), 1);
+        assert_eq!(submitter.unfinished_jobs[0].nounce_start, gone.nounce_start);
+        assert_eq!(submitter.reclaim_offline(now), 0);
+
+        // Another machine picks the reclaimed range up.
+        let again = submitter.next_job("here", "c", Algorithm::Sha256);
+        assert_eq!(again.nounce_start, gone.nounce_start);
+        assert_eq!(submitter.requeue_pending_of("here"), 2);
+    }
+
+    #[test]
+    fn requeued_jobs_are_the_size_of_their_range() {
+        let mut submitter = Submitter::new("s");
+        let job = submitter.next_job("m", "c", Algorithm::Sha256);
+        let job = submitter.pop_pending_job(job.number, "m").unwrap();
+        submitter.finish_job(&job, job.nounce_start + 10);
+        let requeued = submitter.unfinished_jobs.last().unwrap();
+        assert_eq!(requeued.nounce_start, job.nounce_start + 10);
+        assert_eq!(requeued.size, requeued.nounce_end - requeued.nounce_start);
+        assert_eq!(submitter.coverage.gaps(submitter.next_nounce).total(), 0);
+    }
+
+    #[test]
+    fn jobs_stop_at_the_end_of_the_nounce_space() {
+        let mut submitter = Submitter::new("s");
+        submitter.next_nounce = u64::MAX - 10;
+        let job = submitter.next_job("m", "c", Algorithm::Sha256);
+        assert_eq!((job.nounce_start, job.nounce_end, job.size), (u64::MAX - 10, u64::MAX, 10));
+        assert_eq!(submitter.next_nounce, u64::MAX);
+    }
+
+    #[test]
+    fn duplicates_do_not_count_towards_quarantine() {
+        let window = crate::config::get().quarantine_window;
+        let mut submitter = Submitter::new("s");
+        submitter.record_verdicts("m", 0, 0, window);
+        let machine = submitter.get_machine("m");
+        assert_eq!(machine.invalid_shares_count, window);
+        assert_eq!(machine.recent_invalid_ratio(), None);
 
-    pub fn save(&self) {
-        let file  = open_overwrite_file(
-        &format!("submitters/{}", self.student_number),
-        "info.json"
-        ).expect("Could not open/overwrite submitters, info JSON file.");
-        serde_json::to_writer(&file, &self)
-        .expect(&format!("Counld not write JSON to submitters/{}/info.json", self.student_number));
+        submitter.record_verdicts("m", window / 2, window / 2, 0);
+        assert_eq!(submitter.get_machine("m").recent_invalid_ratio(), Some(0.5));
+        assert_eq!(submitter.invalid_shares_count, window + window / 2);
     }
 
-    pub fn save_solution(&self, solution: Solution, leading_zero_bits_length: u8) {
-        let file  = open_append_file(
-            &format!("submitters/{}", self.student_number),
-            &format!("sol_{:02}", leading_zero_bits_length),
-            ).expect("Could not open/append submitters solution file.");
-        serde_json::to_writer(&file, &solution)
-            .expect("Counld not write JSON to submitters solution file");
-        use std::io::Write;
-        writeln!(&file, "").expect("Counld not write line JSON to submitters solution file");
+    #[test]
+    fn quarantine_takes_back_the_machines_jobs() {
+        let mut submitter = Submitter::new("s");
+        submitter.next_job("bad", "c", Algorithm::Sha256);
+        submitter.next_job("good", "c", Algorithm::Sha256);
+        assert_eq!(submitter.quarantine("bad", "invalid", 10.0), 1);
+        assert_eq!(submitter.pending_jobs_of("bad").count(), 0);
+        assert_eq!(submitter.pending_jobs_of("good").count(), 1);
+        assert!(submitter.lift_quarantine("bad"));
+        assert!(!submitter.lift_quarantine("bad"));
+        assert!(!submitter.lift_quarantine("good"));
     }
 }
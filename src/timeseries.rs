@@ -0,0 +1,278 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::storage::Storage;
+
+const HOUR: f64 = 60.0 * 60.0;
+const DAY: f64 = 24.0 * HOUR;
+
+/// How finely a time series is kept. Raw samples are taken every `timeseries_interval` and
+/// averaged into hourly ones, which are kept for longer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Resolution {
+    Raw,
+    Hourly,
+}
+
+crate::util::wire_names!(Resolution {
+    Raw => "raw",
+    Hourly => "hourly",
+});
+
+impl Resolution {
+    /// Seconds of samples each segment holds.
+    fn segment_length(self) -> f64 {
+        match self {
+            Resolution::Raw => DAY,
+            Resolution::Hourly => 30.0 * DAY,
+        }
+    }
+
+    /// Seconds samples are kept for.
+    pub fn retention(self) -> f64 {
+        let config = crate::config::get();
+        match self {
+            Resolution::Raw => config.timeseries_raw_retention,
+            Resolution::Hourly => config.timeseries_retention,
+        }
+    }
+
+    /// Segment a sample taken at `time` belongs in.
+    pub fn segment_of(self, time: f64) -> u64 {
+        (time / self.segment_length()) as u64
+    }
+}
+
+/// The pool's or one submitter's numbers at one moment. Stored with one letter names, every
+/// sample holds a set for each submitter.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct Metrics {
+    /// Reported hashrate of the machines that are not offline.
+    #[serde(rename = "r")]
+    pub reported_hashrate: f64,
+    /// Hashrate from accepted shares over the last 5 minutes.
+    #[serde(rename = "e")]
+    pub effective_hashrate: f64,
+    /// Accepted shares so far.
+    #[serde(rename = "s")]
+    pub shares: u64,
+    /// Work of the accepted shares so far.
+    #[serde(rename = "w")]
+    pub work: f64,
+    /// Machines that are not offline.
+    #[serde(rename = "m")]
+    pub active_machines: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Sample {
+    #[serde(rename = "t")]
+    pub time: f64,
+    #[serde(rename = "p")]
+    pub pool: Metrics,
+    #[serde(rename = "s", default)]
+    pub submitters: BTreeMap<String, Metrics>,
+}
+
+/// Which numbers of a sample to read.
+#[derive(Debug, Clone, Copy)]
+pub enum Series<'a> {
+    Pool,
+    Submitter(&'a str),
+}
+
+impl Sample {
+    /// `None` for a submitter that had not joined yet.
+    pub fn metrics(&self, series: Series) -> Option<Metrics> {
+        match series {
+            Series::Pool => Some(self.pool),
+            Series::Submitter(student_number) => self.submitters.get(student_number).copied(),
+        }
+    }
+}
+
+/// One sample at `time` for `samples`: rates and machine counts are averaged, running totals
+/// are taken from the last sample.
+fn downsample(time: f64, samples: &[Sample]) -> Sample {
+    fn average(metrics: &[Metrics]) -> Metrics {
+        let count = metrics.len().max(1) as f64;
+        let last = metrics.last().copied().unwrap_or_default();
+        Metrics {
+            reported_hashrate: metrics.iter().map(|m| m.reported_hashrate).fold(0.0, |a, b| a + b) / count,
+            effective_hashrate: metrics.iter().map(|m| m.effective_hashrate).fold(0.0, |a, b| a + b) / count,
+            shares: last.shares,
+            work: last.work,
+            active_machines: (metrics.iter().map(|m| m.active_machines).sum::<u64>() as f64 / count).round() as u64,
+        }
+    }
+    let pool: Vec<Metrics> = samples.iter().map(|sample| sample.pool).collect();
+    let mut submitters: BTreeMap<String, Vec<Metrics>> = BTreeMap::new();
+    for sample in samples {
+        for (student_number, metrics) in sample.submitters.iter() {
+            submitters.entry(student_number.clone()).or_default().push(*metrics);
+        }
+    }
+    Sample {
+        time,
+        pool: average(&pool),
+        submitters: submitters.into_iter()
+            .map(|(student_number, metrics)| (student_number, average(&metrics)))
+            .collect(),
+    }
+}
+
+/// Writes samples to storage and averages each hour of them into an hourly sample once it is
+/// over.
+#[derive(Debug, Default)]
+pub struct TimeSeries {
+    /// Hour the held samples were taken in.
+    hour: u64,
+    /// Raw samples of `hour`, not yet downsampled.
+    samples: Vec<Sample>,
+}
+
+impl TimeSeries {
+    /// Picks up the raw samples of the last hour written before a restart, which were not
+    /// downsampled.
+    pub fn load(storage: &dyn Storage, now: f64) -> Self {
+        let segment = Resolution::Raw.segment_of(now);
+        let mut samples = storage.load_segment(Resolution::Raw, segment);
+        if samples.is_empty() && segment > 0 {
+            samples = storage.load_segment(Resolution::Raw, segment - 1);
+        }
+        let hour = match samples.last() {
+            Some(sample) => (sample.time / HOUR) as u64,
+            None => return TimeSeries::default(),
+        };
+        samples.retain(|sample| (sample.time / HOUR) as u64 == hour);
+        TimeSeries { hour, samples }
+    }
+
+    /// Writes `sample`. The first one of a new hour downsamples the previous hour and deletes
+    /// samples past their retention.
+    pub fn record(&mut self, storage: &dyn Storage, sample: Sample) -> std::io::Result<()> {
+        storage.append_sample(Resolution::Raw, Resolution::Raw.segment_of(sample.time), &sample)?;
+        let hour = (sample.time / HOUR) as u64;
+        if hour != self.hour {
+            if !self.samples.is_empty() {
+                let start = self.hour as f64 * HOUR;
+                let hourly = downsample(start, &self.samples);
+                storage.append_sample(Resolution::Hourly, Resolution::Hourly.segment_of(start), &hourly)?;
+            }
+            prune(storage, sample.time)?;
+            self.hour = hour;
+            self.samples.clear();
+        }
+        self.samples.push(sample);
+        Ok(())
+    }
+}
+
+/// Deletes the segments whose every sample is past its retention.
+fn prune(storage: &dyn Storage, now: f64) -> std::io::Result<()> {
+    for resolution in [Resolution::Raw, Resolution::Hourly] {
+        let oldest_kept = resolution.segment_of(now - resolution.retention());
+        for segment in storage.list_segments(resolution) {
+            if segment < oldest_kept {
+                storage.delete_segment(resolution, segment)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `(time, metrics)` of `series` from samples taken in `[from, to]`, oldest first.
+pub fn query(
+    storage: &dyn Storage,
+    resolution: Resolution,
+    series: Series,
+    from: f64,
+    to: f64,
+) -> Vec<(f64, Metrics)> {
+    let mut points = vec![];
+    for segment in resolution.segment_of(from.max(0.0))..=resolution.segment_of(to.max(0.0)) {
+        for sample in storage.load_segment(resolution, segment) {
+            if sample.time < from || sample.time > to {
+                continue;
+            }
+            if let Some(metrics) = sample.metrics(series) {
+                points.push((sample.time, metrics));
+            }
+        }
+    }
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn sample(time: f64, hashrate: f64, shares: u64) -> Sample {
+        let metrics = Metrics {
+            reported_hashrate: hashrate,
+            effective_hashrate: hashrate / 2.0,
+            shares,
+            work: shares as f64,
+            active_machines: 1,
+        };
+        Sample {
+            time,
+            pool: metrics,
+            submitters: std::iter::once((String::from("a"), metrics)).collect(),
+        }
+    }
+
+    #[test]
+    fn downsample_averages_rates_and_keeps_the_last_totals() {
+        let mut late_joiner = sample(60.0, 30.0, 7);
+        late_joiner.submitters.insert(String::from("b"), Metrics { active_machines: 3, ..Metrics::default() });
+        let hourly = downsample(0.0, &[sample(0.0, 10.0, 5), late_joiner]);
+        assert_eq!(hourly.time, 0.0);
+        assert_eq!(hourly.pool.reported_hashrate, 20.0);
+        assert_eq!(hourly.pool.effective_hashrate, 10.0);
+        assert_eq!(hourly.pool.shares, 7);
+        assert_eq!(hourly.pool.work, 7.0);
+        assert_eq!(hourly.pool.active_machines, 1);
+        // Submitters are averaged over the samples they are in.
+        assert_eq!(hourly.submitters["b"].active_machines, 3);
+    }
+
+    #[test]
+    fn record_writes_an_hourly_sample_once_the_hour_is_over() {
+        let storage = MemoryStorage::new();
+        let start = 1000.0 * HOUR;
+        let mut series = TimeSeries::default();
+        series.record(&storage, sample(start, 10.0, 1)).unwrap();
+        series.record(&storage, sample(start + 60.0, 20.0, 2)).unwrap();
+        assert!(query(&storage, Resolution::Hourly, Series::Pool, 0.0, 2.0 * start).is_empty());
+
+        series.record(&storage, sample(start + HOUR, 40.0, 3)).unwrap();
+        let hourly = query(&storage, Resolution::Hourly, Series::Pool, 0.0, 2.0 * start);
+        assert_eq!(hourly.len(), 1);
+        assert_eq!(hourly[0].0, start);
+        assert_eq!(hourly[0].1.reported_hashrate, 15.0);
+        assert_eq!(hourly[0].1.shares, 2);
+        let raw = query(&storage, Resolution::Raw, Series::Submitter("a"), start, start + HOUR);
+        assert_eq!(raw.len(), 3);
+
+        // A restart picks up the samples of the hour in progress.
+        let reloaded = TimeSeries::load(&storage, start + HOUR + 60.0);
+        assert_eq!(reloaded.hour, 1001);
+        assert_eq!(reloaded.samples.len(), 1);
+    }
+
+    #[test]
+    fn record_prunes_segments_past_their_retention() {
+        let storage = MemoryStorage::new();
+        let start = 1000.0 * DAY;
+        let mut series = TimeSeries::default();
+        series.record(&storage, sample(start, 10.0, 1)).unwrap();
+        let later = start + Resolution::Raw.retention() + 2.0 * DAY;
+        series.record(&storage, sample(later, 10.0, 2)).unwrap();
+        assert_eq!(storage.list_segments(Resolution::Raw), vec![Resolution::Raw.segment_of(later)]);
+        assert_eq!(storage.list_segments(Resolution::Hourly).len(), 1);
+    }
+}
@@ -1,8 +1,94 @@
 use std::time::SystemTime;
 
+use rand::RngCore;
+
 pub fn get_time() -> f64 {
     match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
         Ok(n) => n.as_secs_f64(),
         Err(_) => panic!("SystemTime before UNIX EPOCH!"),
     }
-}
\ No newline at end of file
+}
+
+/// `len` random bytes as lowercase hex.
+pub fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// A fresh random challenge for a job or round.
+pub fn random_challenge() -> String {
+    random_hex(16)
+}
+
+/// Decodes a hex string, either case, into bytes.
+pub fn decode_hex(hash: &str) -> Result<Vec<u8>, ()> {
+    if !hash.len().is_multiple_of(2) {
+        return Err(());
+    }
+    let mut buffer = Vec::new();
+    let mut it = hash.chars();
+    while let (Some(upper), Some(lower)) = (it.next(), it.next()) {
+        let mut upper = upper.to_digit(16).ok_or(())? as u8;
+        upper <<= 4;
+        let lower = lower.to_digit(16).ok_or(())? as u8;
+        buffer.push(upper | lower);
+    }
+    Ok(buffer)
+}
+
+/// Escapes text for use in HTML element content or a quoted attribute.
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped += "&amp;",
+            '<' => escaped += "&lt;",
+            '>' => escaped += "&gt;",
+            '"' => escaped += "&quot;",
+            '\'' => escaped += "&#39;",
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Gives a fieldless enum a single table of the names it is sent and parsed as. `name`,
+/// `FromStr` and serde are all derived from it, so they can not drift apart.
+macro_rules! wire_names {
+    ($type:ident { $($variant:ident => $name:literal),+ $(,)? }) => {
+        impl $type {
+            /// Name the value is sent and parsed as.
+            pub fn name(self) -> &'static str {
+                match self {
+                    $($type::$variant => $name,)+
+                }
+            }
+        }
+
+        impl std::str::FromStr for $type {
+            type Err = String;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s {
+                    $($name => Ok($type::$variant),)+
+                    _ => Err(format!("expected one of {}", [$(concat!("'", $name, "'")),+].join(", "))),
+                }
+            }
+        }
+
+        impl serde::Serialize for $type {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(self.name())
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $type {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let name = String::deserialize(deserializer)?;
+                name.parse().map_err(serde::de::Error::custom)
+            }
+        }
+    };
+}
+pub(crate) use wire_names;